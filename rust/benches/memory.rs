@@ -0,0 +1,58 @@
+//! How much memory the reducers hold on to. Run with
+//! `cargo bench --features bench`.
+//!
+//! This is kept apart from `reduction`, since counting allocations slows
+//! down every one of them.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lambdawasm::bench;
+
+// The system allocator, keeping count of the bytes in use and of the most
+// there have been since `peak` was last reset.
+struct Counting {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = self.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        self.peak.fetch_max(current, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.current.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+// Run `f` and print the most memory it had allocated at any one time.
+fn peak<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let before = ALLOCATOR.current.load(Ordering::Relaxed);
+    ALLOCATOR.peak.store(before, Ordering::Relaxed);
+    let result = f();
+    let peak = ALLOCATOR.peak.load(Ordering::Relaxed) - before;
+    println!("{:<40} {:>8} KiB", name, peak / 1024);
+    result
+}
+
+fn main() {
+    for (base, exponent) in [(3, 3), (2, 6), (3, 4)] {
+        let input = format!("(λb e.e b) {} {}", base, exponent);
+        // Every term of the reduction at once, as a trace shows them.
+        let terms = peak(
+            &format!("keep the trace of EXP {} {}", base, exponent),
+            || bench::keep_trace(&input, 1_000_000),
+        );
+        println!("  {} terms", terms);
+    }
+}
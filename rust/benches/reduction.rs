@@ -0,0 +1,76 @@
+//! Timings for the reducers. Run with `cargo bench --features bench`.
+
+use std::time::{Duration, Instant};
+
+use lambdawasm::bench::{self, Machine};
+
+// Run `f` in batches for a second and print the mean time per run of the
+// fastest batch, which is the one least disturbed by anything else running.
+fn time(name: &str, mut f: impl FnMut()) {
+    let start = Instant::now();
+    let mut best = Duration::MAX;
+    while start.elapsed() < Duration::from_secs(1) {
+        let batch = Instant::now();
+        let mut runs = 0;
+        while runs == 0 || batch.elapsed() < Duration::from_millis(50) {
+            f();
+            runs += 1;
+        }
+        best = best.min(batch.elapsed() / runs);
+    }
+    println!("{:<40} {:>12.3?}", name, best);
+}
+
+// `λx0.λx1. ... λx{n-1}.x0 x1 ... x{n-1}`: every binder's scope mentions
+// every binder around it.
+fn nested_binders(n: usize) -> String {
+    let binders: String = (0..n).map(|i| format!("λx{}.", i)).collect();
+    let body: Vec<String> = (0..n).map(|i| format!("x{}", i)).collect();
+    format!("{}{}", binders, body.join(" "))
+}
+
+// `(λr.λx0. ... λx{n-1}.r) (y0 y1 ... y{n-1})`: a term with `n` free
+// variables substituted under `n` binders.
+fn substitution_under_binders(n: usize) -> String {
+    let binders: String = (0..n).map(|i| format!("λx{}.", i)).collect();
+    let argument: Vec<String> = (0..n).map(|i| format!("y{}", i)).collect();
+    format!("(λr.{}r) ({})", binders, argument.join(" "))
+}
+
+fn main() {
+    const EXP: &str = "(λb e.e b)";
+    for (base, exponent) in [(3, 3), (2, 6), (3, 4)] {
+        let input = format!("{} {} {}", EXP, base, exponent);
+        let (_, steps) = bench::normalize(&input, 1_000_000);
+        time(
+            &format!("normalize EXP {} {} ({} steps)", base, exponent, steps),
+            || {
+                bench::normalize(&input, 1_000_000);
+            },
+        );
+    }
+    // `2^6` applied to the identity and then to another takes either
+    // machine through 64 applications of the first.
+    let input = format!("{} 2 6 (λy.y) λx.x", EXP);
+    for machine in [Machine::Krivine, Machine::Cek] {
+        let transitions = bench::run_machine(&input, machine, 1_000_000);
+        time(
+            &format!("{:?} on EXP 2 6 I I ({} transitions)", machine, transitions),
+            || {
+                bench::run_machine(&input, machine, 1_000_000);
+            },
+        );
+    }
+    for n in [100, 400, 1600] {
+        let input = substitution_under_binders(n);
+        time(&format!("substitute under {} binders", n), || {
+            bench::normalize(&input, 1);
+        });
+    }
+    for n in [100, 400, 1600] {
+        let input = nested_binders(n);
+        time(&format!("read back {} nested binders", n), || {
+            bench::read_back(&input);
+        });
+    }
+}
@@ -0,0 +1,142 @@
+//! α-equivalence: comparing terms up to the names of their bound variables.
+//!
+//! Every term has a canonical α-variant in which each binder is named after
+//! its depth, the number of binders enclosing it: `a` for the outermost,
+//! `b` for the next, and so on, skipping any name that occurs free in the
+//! term. Depth grows along every path, so no binder shadows another, and two
+//! terms are α-equivalent exactly when their canonical variants are equal.
+
+use std::collections::HashSet;
+
+use super::nameless::Term;
+use super::strategy::Branch;
+use super::traverse::{self, fold, Children, Fold, Scope};
+use super::{free_vars, AST};
+
+/// Rename every binder of `ast` canonically.
+pub fn canonical(ast: &AST) -> AST {
+    let names = Names {
+        free: free_vars(ast),
+        taken: Vec::new(),
+        next: 0,
+    };
+    let mut renamer = Renamer {
+        names,
+        scope: Scope::new(),
+        depth: 0,
+    };
+    fold(ast, &mut renamer)
+}
+
+/// Whether `a` and `b` differ only in the names of bound variables. This
+/// compares their nameless forms, which is cheaper than renaming both.
+pub fn alpha_equivalent(a: &AST, b: &AST) -> bool {
+    Term::from_ast(a).alpha_eq(&Term::from_ast(b))
+}
+
+struct Names {
+    free: HashSet<String>,
+    // The canonical name for each depth, chosen the first time it is needed.
+    taken: Vec<String>,
+    // The next candidate to try.
+    next: usize,
+}
+
+impl Names {
+    fn at_depth(&mut self, depth: usize) -> String {
+        while self.taken.len() <= depth {
+            let name = candidate(self.next);
+            self.next += 1;
+            if !self.free.contains(&name) {
+                self.taken.push(name);
+            }
+        }
+        self.taken[depth].clone()
+    }
+}
+
+// `a` to `z`, then `a1` to `z1`, and so on.
+fn candidate(n: usize) -> String {
+    let letter = (b'a' + (n % 26) as u8) as char;
+    match n / 26 {
+        0 => letter.to_string(),
+        round => format!("{}{}", letter, round),
+    }
+}
+
+struct Renamer<'a> {
+    names: Names,
+    // The new name of each enclosing binder.
+    scope: Scope<'a, String>,
+    // How many binders enclose the current node.
+    depth: usize,
+}
+
+impl<'a> Fold<'a, AST> for Renamer<'a> {
+    type Output = AST;
+
+    fn visit(&mut self, node: &'a AST) -> Option<AST> {
+        match node {
+            AST::Var(name) => Some(AST::Var(self.scope.get(name).unwrap_or(name).clone())),
+            _ => None,
+        }
+    }
+
+    fn descend(&mut self, node: &'a AST, branch: Branch) {
+        if let Some(name) = traverse::binder(node, branch) {
+            let new = self.names.at_depth(self.depth);
+            self.scope.bind(name, new);
+            self.depth += 1;
+        }
+    }
+
+    fn ascend(&mut self, node: &'a AST, branch: Branch) {
+        if let Some(name) = traverse::binder(node, branch) {
+            self.scope.unbind(name);
+            self.depth -= 1;
+        }
+    }
+
+    fn build(&mut self, node: &'a AST, children: Children<AST>) -> AST {
+        let mut ast = traverse::rebuild(node, children);
+        if let AST::Lambda { param: name, .. } | AST::Let { name, .. } = &mut ast {
+            *name = self.names.at_depth(self.depth);
+        }
+        ast
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    fn parse_default(input: &str) -> AST {
+        parse(input, ParseOptions::default()).expect("the term parses")
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        let equivalent = |a: &str, b: &str| alpha_equivalent(&parse_default(a), &parse_default(b));
+        assert!(equivalent("λx.x", "λy.y"));
+        assert!(equivalent("λx y.x y", "λa b.a b"));
+        assert!(equivalent("λx.λx.x", "λy.λz.z"));
+        // Free variables must match by name.
+        assert!(!equivalent("λx.y", "λx.z"));
+        assert!(!equivalent("λx.y", "λy.y"));
+        assert!(!equivalent("λx y.x", "λx y.y"));
+        assert!(!equivalent("λx.x x", "λx.x"));
+    }
+
+    #[test]
+    fn canonical_renaming() {
+        let canonical =
+            |input: &str| ast_to_string(&canonical(&parse_default(input)), PrintOptions::default());
+        assert_eq!(canonical("λx y.x y"), "λa.λb.a b");
+        // Names free in the term are skipped, and sibling binders share
+        // their depth's name.
+        assert_eq!(canonical("λx.a (λy.y x) (λz.z)"), "λb.a (λc.c b) λc.c");
+        assert_eq!(canonical("λx.λx.x"), "λa.λb.b");
+        assert_eq!(canonical("λp q.q p"), canonical("λu v.v u"));
+    }
+}
@@ -0,0 +1,48 @@
+//! Native entry points for the benchmarks in `benches/`, which cannot pass
+//! options as a `JsValue`. Only built with the `bench` feature.
+
+use super::machine;
+pub use super::machine::Kind as Machine;
+use super::nameless::Term;
+use super::strategy::{self, Reduction};
+use super::{
+    ast_to_string, normalize_internal, parse, Options, ParseOptions, PrintOptions, Strategy,
+};
+
+/// Normalize in normal order, returning the normal form and the number of
+/// steps taken.
+pub fn normalize(input: &str, max_steps: usize) -> (String, usize) {
+    let report =
+        normalize_internal(input, max_steps, Options::default()).expect("benchmark terms parse");
+    (report.term, report.steps)
+}
+
+/// Run an abstract machine without a trace, returning the number of
+/// transitions it made.
+pub fn run_machine(input: &str, machine: Machine, max_steps: usize) -> usize {
+    let ast = parse(input, ParseOptions::default()).expect("benchmark terms parse");
+    machine::run(&ast, machine, max_steps, false).steps
+}
+
+/// Convert a term to the nameless core and read it back.
+pub fn read_back(input: &str) -> String {
+    let ast = parse(input, ParseOptions::default()).expect("benchmark terms parse");
+    let term = Term::from_ast(&ast);
+    ast_to_string(&term.to_ast(&mut Vec::new()), PrintOptions::default())
+}
+
+/// Normalize in normal order holding on to every term along the way, and
+/// return how many there are.
+pub fn keep_trace(input: &str, max_steps: usize) -> usize {
+    let ast = parse(input, ParseOptions::default()).expect("benchmark terms parse");
+    let mut trace = vec![Term::from_ast(&ast)];
+    while trace.len() <= max_steps {
+        let term = trace.last().expect("the trace starts with the term");
+        let Some(choice) = strategy::choose(term, Strategy::NormalOrder, Reduction::Beta) else {
+            break;
+        };
+        let next = choice.apply(term).term;
+        trace.push(next);
+    }
+    trace.len()
+}
@@ -0,0 +1,165 @@
+//! Complete developments: contracting every redex of a term at once.
+//!
+//! Following Takahashi, the complete development `M*` of a term is
+//!
+//! * `x* = x`
+//! * `(λx.M)* = λx.M*`
+//! * `((λx.M) N)* = M*[x := N*]`
+//! * `(M N)* = M* N*` when `M` is not an abstraction
+//!
+//! so every redex of `M` is contracted, nested ones included, but none of
+//! the redexes that contracting them creates. Repeating complete
+//! developments reaches the normal form whenever there is one. A kept `let`
+//! is expanded after developing its parts, and under `Reduction::BetaEta`
+//! an η-redex `(λx.M x)*` is `M*`.
+
+use super::nameless::{Node, Term};
+use super::strategy::{Branch, Contraction, Reduction, StepKind};
+use super::traverse::{fold, Children, Fold};
+
+/// Develop `term` completely, or return `None` if it has no redexes.
+pub fn develop(term: &Term, reduction: Reduction) -> Option<Contraction> {
+    let mut developer = Developer {
+        reduction,
+        path: Vec::new(),
+        developed: Vec::new(),
+        inside_redex: false,
+        contracting: Vec::new(),
+    };
+    let developed_term = fold(term, &mut developer);
+    let mut developed = developer.developed.into_iter();
+    let (path, kind) = developed.next()?;
+    let mut paths = vec![path.clone()];
+    paths.extend(developed.map(|(path, _)| path));
+    // Reducts end up nested in one another, so the whole term is respelled.
+    let mut renamed = Vec::new();
+    let term = match developed_term.respelled(&[], &mut renamed) {
+        Some(respelled) => respelled,
+        None => developed_term,
+    };
+    Some(Contraction {
+        term,
+        path,
+        kind,
+        renamed,
+        developed: paths,
+    })
+}
+
+struct Developer {
+    reduction: Reduction,
+    // From the root of the original term to the node being developed.
+    path: Vec<Branch>,
+    // Every redex contracted, in pre-order.
+    developed: Vec<(Vec<Branch>, StepKind)>,
+    // Whether the node about to be visited is the abstraction of a β-redex
+    // or the body of an η-redex, which are only developed inside.
+    inside_redex: bool,
+    // The kind of redex each node being developed is contracted as, if it
+    // is one, innermost last.
+    contracting: Vec<Option<StepKind>>,
+}
+
+impl<'t> Fold<'t, Term> for Developer {
+    type Output = Term;
+
+    fn visit(&mut self, term: &'t Term) -> Option<Term> {
+        let inside_redex = std::mem::take(&mut self.inside_redex);
+        let kind = match &**term {
+            Node::Bound(_) | Node::Free(_) | Node::Error => return Some(term.clone()),
+            _ if inside_redex => None,
+            Node::Lambda { .. } if self.reduction == Reduction::BetaEta && term.is_eta_redex() => {
+                Some(StepKind::Eta)
+            }
+            Node::App(left, _) if matches!(**left, Node::Lambda { .. }) => Some(StepKind::Beta),
+            Node::Let { .. } => Some(StepKind::Let),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            self.developed.push((self.path.clone(), kind));
+        }
+        self.contracting.push(kind);
+        None
+    }
+
+    fn descend(&mut self, _: &'t Term, branch: Branch) {
+        self.path.push(branch);
+        self.inside_redex = matches!(
+            (self.contracting.last(), branch),
+            (Some(Some(StepKind::Eta)), Branch::Body)
+                | (Some(Some(StepKind::Beta)), Branch::Function)
+        );
+    }
+
+    fn ascend(&mut self, _: &'t Term, _: Branch) {
+        self.path.pop();
+    }
+
+    fn build(&mut self, term: &'t Term, children: Children<Term>) -> Term {
+        let kind = self.contracting.pop().expect("node visited");
+        match (kind, children) {
+            (Some(StepKind::Eta), Children::One(body)) => match &*body {
+                // Developing adds no references to the variable.
+                Node::App(function, _) => function.shifted(-1, 0),
+                _ => unreachable!("an η-redex abstracts an application"),
+            },
+            (Some(StepKind::Beta), Children::Two(function, argument)) => match &*function {
+                Node::Lambda { body, .. } => body.instantiate(&argument),
+                _ => unreachable!("a β-redex applies an abstraction"),
+            },
+            (Some(StepKind::Let), children) => term.rebuild(children).expand_let(),
+            (_, children) => term.rebuild(children),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nameless::Term;
+    use crate::strategy::{step, Branch, Reduction, StepKind, Strategy};
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    // One complete development of `input`: the term after it and the paths
+    // of the redexes it contracted.
+    fn develop(input: &str, reduction: Reduction) -> (String, Vec<Vec<Branch>>, StepKind) {
+        let ast = parse(input, ParseOptions::default()).expect("the term parses");
+        let contraction =
+            step(&Term::from_ast(&ast), Strategy::Parallel, reduction).expect("a redex");
+        let term = ast_to_string(
+            &contraction.term.to_ast(&mut Vec::new()),
+            PrintOptions::default(),
+        );
+        (term, contraction.developed, contraction.kind)
+    }
+
+    #[test]
+    fn development_paths() {
+        use Branch::*;
+        // Both redexes at once, the outer one first.
+        let (term, paths, _) = develop("(λx.x x) ((λy.y) a)", Reduction::Beta);
+        assert_eq!(term, "a a");
+        assert_eq!(paths, [vec![], vec![Argument]]);
+        // A redex inside the abstraction of another is contracted too.
+        let (term, paths, _) = develop("(λx.(λy.y) x) b ((λz.z) c)", Reduction::Beta);
+        assert_eq!(term, "b c");
+        assert_eq!(
+            paths,
+            [
+                vec![Function],
+                vec![Function, Function, Body],
+                vec![Argument]
+            ]
+        );
+        // The redex contracting `(λx.x a) (λy.y)` creates is left alone.
+        let (term, paths, _) = develop("(λx.x a) (λy.y)", Reduction::Beta);
+        assert_eq!(term, "(λy.y) a");
+        assert_eq!(paths, [Vec::<Branch>::new()]);
+        // Under βη, the body of an η-redex is only developed inside.
+        let (term, paths, kind) = develop("λz.(λx.x) z", Reduction::BetaEta);
+        assert_eq!((term.as_str(), kind), ("λx.x", StepKind::Eta));
+        assert_eq!(paths, [Vec::<Branch>::new()]);
+        let (term, paths, kind) = develop("λz.(λx.x) z", Reduction::Beta);
+        assert_eq!((term.as_str(), kind), ("λz.z", StepKind::Beta));
+        assert_eq!(paths, [vec![Body]]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Surface syntaxes. Every dialect reads into the same `AST` and can be
+//! printed back out, so a term can be converted from one notation to another.
+//!
+//! * `Standard`: `λx y.M` (or `\x.M`), `--` and `{- -}` comments.
+//! * `Haskell`: `\x y -> M`, with Haskell's comments.
+//! * `Lisp`: `(lambda (x y) M)`, `(f a b)`, `(define NAME M)`, `;` comments.
+//! * `Latex`: `\lambda x.\, M`, with `{ }` for grouping, spacing commands
+//!   such as `\,` and `\;` ignored, and `%` comments.
+
+use serde::Deserialize;
+
+use super::{
+    ast_to_string, expand_lets, pretty, ParseError, Parser, PrintOptions, Span, Token, TokenKind,
+    AST, ERROR_NAME,
+};
+use crate::program::Program;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Dialect {
+    #[default]
+    Standard,
+    Haskell,
+    Lisp,
+    Latex,
+}
+
+impl Dialect {
+    pub fn starts_line_comment(self, c: char, following: Option<char>) -> bool {
+        match self {
+            Dialect::Standard | Dialect::Haskell => c == '-' && following == Some('-'),
+            Dialect::Lisp => c == ';',
+            Dialect::Latex => c == '%',
+        }
+    }
+
+    pub fn has_block_comments(self) -> bool {
+        matches!(self, Dialect::Standard | Dialect::Haskell)
+    }
+
+    // The token between a lambda's binders and its body.
+    pub fn binder_separator(self) -> Token {
+        match self {
+            Dialect::Haskell => Token::Arrow,
+            _ => Token::Dot,
+        }
+    }
+
+    // How an abstraction is written: the lambda itself, the text between
+    // two binders, the separator after them, and the space between the
+    // separator and the body when they share a line.
+    pub fn binder_syntax(self) -> (&'static str, &'static str, &'static str, &'static str) {
+        match self {
+            Dialect::Standard | Dialect::Lisp => ("λ", " ", ".", ""),
+            Dialect::Haskell => ("\\", " ", " ->", " "),
+            Dialect::Latex => ("\\lambda ", "\\, ", ".", "\\, "),
+        }
+    }
+
+    // The space between a function and its argument on one line.
+    pub fn application_space(self) -> &'static str {
+        match self {
+            Dialect::Latex => "\\; ",
+            _ => " ",
+        }
+    }
+}
+
+// LaTeX commands that only affect spacing and are skipped while reading.
+pub const LATEX_SPACING: &[&str] = &[",", ";", ":", "!", " ", "quad", "qquad"];
+
+// An S-expression list the parser has opened but not yet closed.
+enum List {
+    // `(f a ...)` opened at `start`, with the elements read so far applied
+    // to each other.
+    Application {
+        start: usize,
+        function: Option<AST>,
+    },
+    // `(lambda (params) ...)`, waiting for its body.
+    Lambda {
+        params: Vec<String>,
+        starts: Vec<usize>,
+    },
+}
+
+impl Parser {
+    // Parse one S-expression: an atom, `(lambda (x ...) body)` or an
+    // application `(f a ...)`. The lists still open are kept on a stack of
+    // their own, innermost last.
+    pub(crate) fn parse_sexpr(&mut self) -> Result<AST, ParseError> {
+        let mut lists = Vec::new();
+        loop {
+            let start = self.pos;
+            let mut expr = match self.peek() {
+                Some(Token::LParen) => {
+                    self.check_nesting(lists.len())?;
+                    self.next();
+                    self.depth += 1;
+                    if self.peek() == Some(&Token::Lambda) {
+                        self.next();
+                        let (params, starts) = self.parse_lisp_binders()?;
+                        lists.push(List::Lambda { params, starts });
+                    } else {
+                        lists.push(List::Application {
+                            start,
+                            function: None,
+                        });
+                    }
+                    continue;
+                }
+                Some(Token::Identifier(_) | Token::Number(_)) => self.parse_atom()?,
+                _ => {
+                    let message = match self.peek() {
+                        Some(tok) => format!("Unexpected token: {:?}", tok),
+                        None => "Unexpected end of input".to_string(),
+                    };
+                    let error = self.error(&message, &[TokenKind::Identifier, TokenKind::LParen]);
+                    self.report(error)?;
+                    if self.peek().is_some()
+                        && (self.peek() != Some(&Token::RParen) || self.depth == 0)
+                    {
+                        self.next();
+                    }
+                    self.located(start, 0);
+                    AST::Error
+                }
+            };
+            // Hand the finished expression to the innermost list, and close
+            // each list it completes.
+            loop {
+                let start = match lists.pop() {
+                    None => return Ok(expr),
+                    Some(List::Application { start, function }) => {
+                        if let Some(function) = function {
+                            expr = AST::App(Box::new(function), Box::new(expr));
+                            self.located(start, 2);
+                        }
+                        if !matches!(self.peek(), None | Some(Token::RParen)) {
+                            lists.push(List::Application {
+                                start,
+                                function: Some(expr),
+                            });
+                            break;
+                        }
+                        start
+                    }
+                    Some(List::Lambda { params, starts }) => {
+                        expr = self.located_lambdas(params, &starts, expr);
+                        starts[0]
+                    }
+                };
+                self.depth -= 1;
+                self.close_paren()?;
+                // The node for the whole list includes its parentheses.
+                let span = Span::new(self.span_at(start).start, self.span_at(self.pos - 1).end);
+                if let Some(tree) = self.spans.as_mut().and_then(|spans| spans.last_mut()) {
+                    tree.span = span;
+                }
+            }
+        }
+    }
+
+    // The binders of `(lambda (x ...) body)` after `lambda`, and where the
+    // abstraction for each starts: the outermost at `(lambda`, the others
+    // at their binders.
+    fn parse_lisp_binders(&mut self) -> Result<(Vec<String>, Vec<usize>), ParseError> {
+        let mut starts = vec![self.pos - 2];
+        let mut params = Vec::new();
+        if self.peek() == Some(&Token::LParen) {
+            self.next();
+            while let Some(Token::Identifier(param)) = self.peek() {
+                if !params.is_empty() {
+                    starts.push(self.pos);
+                }
+                params.push(param.clone());
+                self.next();
+            }
+            self.close_paren()?;
+        } else if let Some(Token::Identifier(param)) = self.peek() {
+            // `(lambda x body)` for a single binder.
+            params.push(param.clone());
+            self.next();
+        }
+        if params.is_empty() {
+            self.report(self.error("Expected identifier after lambda", &[TokenKind::Identifier]))?;
+            params.push(ERROR_NAME.to_string());
+        }
+        Ok((params, starts))
+    }
+
+    // Parse `(define NAME term)` forms followed by the main term.
+    pub(crate) fn parse_lisp_program(&mut self) -> Result<Program, ParseError> {
+        let mut definitions = Vec::new();
+        while let (Some(Token::LParen), Some(Token::Identifier(keyword))) =
+            (self.peek(), self.peek_at(1))
+        {
+            if keyword != "define" {
+                break;
+            }
+            self.next();
+            self.next();
+            let name = match self.peek() {
+                Some(Token::Identifier(name)) => name.clone(),
+                _ => {
+                    self.report(
+                        self.error("Expected identifier after define", &[TokenKind::Identifier]),
+                    )?;
+                    ERROR_NAME.to_string()
+                }
+            };
+            let name_pos = self.pos;
+            if name != ERROR_NAME {
+                self.next();
+            }
+            let body = self.parse_sexpr()?;
+            self.close_paren()?;
+            definitions.push(self.definition(name, name_pos, body));
+        }
+        let main = self.parse_sexpr()?;
+        Ok(Program { definitions, main })
+    }
+}
+
+/// Print a term as an S-expression.
+pub fn print_lisp(ast: &AST, options: PrintOptions) -> String {
+    // Lisp has no `let` of ours to read back, so print what it means.
+    pretty::render(&pretty::term(&expand_lets(ast), options), usize::MAX)
+}
+
+/// Print a whole program, definitions first, without expanding them.
+pub fn print_program(program: &Program, options: PrintOptions) -> String {
+    let mut out = String::new();
+    for def in &program.definitions {
+        let body = ast_to_string(&def.body, options);
+        if options.dialect == Dialect::Lisp {
+            out.push_str(&format!("(define {} {})\n", def.name, body));
+        } else {
+            out.push_str(&format!("{} = {};\n", def.name, body));
+        }
+    }
+    out.push_str(&ast_to_string(&program.main, options));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{convert_internal, Options, ParseOptions};
+
+    const DIALECTS: [Dialect; 4] = [
+        Dialect::Standard,
+        Dialect::Haskell,
+        Dialect::Lisp,
+        Dialect::Latex,
+    ];
+
+    fn convert(input: &str, from: Dialect, to: Dialect) -> String {
+        let options = Options {
+            parse: ParseOptions {
+                dialect: from,
+                ..ParseOptions::default()
+            },
+            print: PrintOptions {
+                dialect: to,
+                compact_binders: true,
+                ..PrintOptions::default()
+            },
+            ..Options::default()
+        };
+        convert_internal(input, options).expect("the program parses")
+    }
+
+    #[test]
+    fn conversion_between_dialects() {
+        let expected = [
+            "K = λx y.x;\nK (λz.z z) (f a b)",
+            "K = \\x y -> x;\nK (\\z -> z z) (f a b)",
+            "(define K (lambda (x y) x))\n(K (lambda (z) (z z)) (f a b))",
+            "K = \\lambda x\\, y.\\, x;\nK\\; (\\lambda z.\\, z\\; z)\\; (f\\; a\\; b)",
+        ];
+        let input = "K = λx y.x; -- constant\nK (λz.z z) (f a b)";
+        for (to, output) in DIALECTS.into_iter().zip(expected) {
+            assert_eq!(convert(input, Dialect::Standard, to), output);
+            // Every dialect reads back what every other one prints.
+            for (from, source) in DIALECTS.into_iter().zip(expected) {
+                assert_eq!(convert(source, from, to), output, "{from:?} to {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn dialect_comments() {
+        let inputs = [
+            (Dialect::Haskell, "{- id -} \\x -> x -- done"),
+            (Dialect::Lisp, "; id\n(lambda (x) x)"),
+            (Dialect::Latex, "% id\n\\lambda x.\\, x"),
+        ];
+        for (from, input) in inputs {
+            assert_eq!(convert(input, from, Dialect::Standard), "λx.x");
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Persistent environments for the evaluators: lists of bindings, innermost
+//! first, shared between the closures built in them.
+//!
+//! A binding may hold a closure, whose environment holds closures in turn,
+//! so environments nest as deeply as evaluation goes. Dropping one takes
+//! the bindings apart from an explicit stack rather than recursively.
+
+use std::rc::Rc;
+
+/// An environment, `None` when it is empty.
+pub type Env<N, V> = Option<Rc<Binding<N, V>>>;
+
+pub struct Binding<N, V: Value<N>> {
+    pub name: N,
+    pub value: V,
+    next: Env<N, V>,
+}
+
+/// What a variable can be bound to.
+pub trait Value<N>: Sized {
+    /// Take out the environment the value holds, if it holds one.
+    fn take_env(&mut self) -> Env<N, Self> {
+        None
+    }
+}
+
+impl<N, V: Value<N>> Drop for Binding<N, V> {
+    fn drop(&mut self) {
+        // Unlink the rest of the list and the environment of the value, and
+        // theirs, one binding at a time, stopping at any that is still
+        // shared.
+        let mut orphans: Vec<_> = [self.next.take(), self.value.take_env()]
+            .into_iter()
+            .flatten()
+            .collect();
+        while let Some(binding) = orphans.pop() {
+            if let Ok(mut binding) = Rc::try_unwrap(binding) {
+                orphans.extend(binding.next.take());
+                orphans.extend(binding.value.take_env());
+            }
+        }
+    }
+}
+
+/// `env` with `name` bound to `value` in front.
+pub fn bind<N, V: Value<N>>(env: &Env<N, V>, name: N, value: V) -> Env<N, V> {
+    Some(Rc::new(Binding {
+        name,
+        value,
+        next: env.clone(),
+    }))
+}
+
+/// The bindings of `env`, innermost first.
+pub fn bindings<N, V: Value<N>>(env: &Env<N, V>) -> impl Iterator<Item = &Binding<N, V>> {
+    std::iter::successors(env.as_deref(), |binding| binding.next.as_deref())
+}
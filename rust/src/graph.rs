@@ -0,0 +1,322 @@
+//! The reduction graph of a term: every term reachable by contracting one
+//! redex at a time, identified up to α-equivalence, with an edge for every
+//! single contraction. Exploration is breadth first and stops at a given
+//! number of nodes and a given depth, so the graph of a term that reduces
+//! forever is a finite part of it.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::nameless::Term;
+use super::strategy::{Branch, Reduction, StepKind};
+use super::{alpha, ast_to_string, redex, PrintOptions, AST};
+
+pub struct Node {
+    pub term: AST,
+    /// The length of the shortest reduction from the root.
+    pub depth: usize,
+    pub normal_form: bool,
+    /// Whether every contraction from here was followed. Nodes at the depth
+    /// limit, and nodes whose successors did not fit in the size limit, are
+    /// only partly explored.
+    pub expanded: bool,
+}
+
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// Where the contracted redex is in the `from` term.
+    pub path: Vec<Branch>,
+    pub kind: StepKind,
+    /// On the shortest path found from the root to a normal form.
+    pub shortest: bool,
+    /// On the longest path found from the root to a normal form.
+    pub longest: bool,
+}
+
+pub struct Graph {
+    /// The root is node 0.
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// Whether every node was fully explored.
+    pub complete: bool,
+    /// Whether a normal form can be reached through a cycle, so reductions
+    /// to it can be arbitrarily long and no longest path is marked.
+    pub longest_unbounded: bool,
+}
+
+/// Explore the reduction graph of `ast` breadth first, adding at most
+/// `max_nodes` nodes and following reductions at most `max_depth` steps.
+pub fn explore(ast: &AST, reduction: Reduction, max_nodes: usize, max_depth: usize) -> Graph {
+    let mut nodes = vec![Node {
+        term: ast.clone(),
+        depth: 0,
+        normal_form: false,
+        expanded: false,
+    }];
+    let mut index = HashMap::from([(alpha::canonical(ast), 0)]);
+    // The edge each node was first reached by, which is on a shortest path.
+    let mut parent: Vec<Option<usize>> = vec![None];
+    let mut edges = Vec::new();
+    let mut queue = VecDeque::from([0]);
+    while let Some(from) = queue.pop_front() {
+        let term = nodes[from].term.clone();
+        let redexes = redex::redexes(&term, reduction);
+        nodes[from].normal_form = redexes.is_empty();
+        if nodes[from].depth == max_depth && !redexes.is_empty() {
+            continue;
+        }
+        let mut expanded = true;
+        let nameless = Term::from_ast(&term);
+        for found in redexes {
+            let contraction =
+                redex::reduce_at(&nameless, &found.path).expect("listed redexes contract");
+            let after = contraction.term.to_ast(&mut Vec::new());
+            let key = alpha::canonical(&after);
+            let to = match index.get(&key) {
+                Some(&to) => to,
+                None if nodes.len() < max_nodes => {
+                    index.insert(key, nodes.len());
+                    parent.push(Some(edges.len()));
+                    queue.push_back(nodes.len());
+                    nodes.push(Node {
+                        term: after,
+                        depth: nodes[from].depth + 1,
+                        normal_form: false,
+                        expanded: false,
+                    });
+                    nodes.len() - 1
+                }
+                None => {
+                    expanded = false;
+                    continue;
+                }
+            };
+            edges.push(Edge {
+                from,
+                to,
+                path: found.path,
+                kind: found.kind,
+                shortest: false,
+                longest: false,
+            });
+        }
+        nodes[from].expanded = expanded;
+    }
+    let complete = nodes.iter().all(|node| node.expanded);
+    let mut graph = Graph {
+        nodes,
+        edges,
+        complete,
+        longest_unbounded: false,
+    };
+    // Breadth-first order finds the nearest normal form first.
+    if let Some(target) = graph.nodes.iter().position(|node| node.normal_form) {
+        mark(&mut graph.edges, &parent, target, |edge| {
+            edge.shortest = true
+        });
+    }
+    mark_longest(&mut graph);
+    graph
+}
+
+// Mark the edges on the path to `target` given by `predecessor`.
+fn mark(edges: &mut [Edge], predecessor: &[Option<usize>], target: usize, set: fn(&mut Edge)) {
+    let mut node = target;
+    while let Some(edge) = predecessor[node] {
+        set(&mut edges[edge]);
+        node = edges[edge].from;
+    }
+}
+
+// Find the longest path from the root to a normal form among the nodes
+// that lead to one. That part of the graph must be acyclic for it to exist.
+fn mark_longest(graph: &mut Graph) {
+    let n = graph.nodes.len();
+    let mut leads_to_normal_form: Vec<bool> =
+        graph.nodes.iter().map(|node| node.normal_form).collect();
+    let mut incoming = vec![Vec::new(); n];
+    for (i, edge) in graph.edges.iter().enumerate() {
+        incoming[edge.to].push(i);
+    }
+    let mut stack: Vec<usize> = (0..n).filter(|&i| leads_to_normal_form[i]).collect();
+    while let Some(node) = stack.pop() {
+        for &edge in &incoming[node] {
+            let from = graph.edges[edge].from;
+            if !leads_to_normal_form[from] {
+                leads_to_normal_form[from] = true;
+                stack.push(from);
+            }
+        }
+    }
+    let relevant: Vec<usize> = (0..graph.edges.len())
+        .filter(|&i| {
+            let edge = &graph.edges[i];
+            leads_to_normal_form[edge.from] && leads_to_normal_form[edge.to]
+        })
+        .collect();
+    // Kahn's algorithm, relaxing longest distances in topological order.
+    let mut in_degree = vec![0; n];
+    let mut outgoing = vec![Vec::new(); n];
+    for &i in &relevant {
+        in_degree[graph.edges[i].to] += 1;
+        outgoing[graph.edges[i].from].push(i);
+    }
+    let mut ready: Vec<usize> = (0..n)
+        .filter(|&i| leads_to_normal_form[i] && in_degree[i] == 0)
+        .collect();
+    let mut distance: Vec<Option<usize>> = vec![None; n];
+    distance[0] = Some(0);
+    let mut predecessor = vec![None; n];
+    let mut visited = 0;
+    while let Some(node) = ready.pop() {
+        visited += 1;
+        for &i in &outgoing[node] {
+            let to = graph.edges[i].to;
+            if let Some(d) = distance[node] {
+                if distance[to].is_none_or(|current| d + 1 > current) {
+                    distance[to] = Some(d + 1);
+                    predecessor[to] = Some(i);
+                }
+            }
+            in_degree[to] -= 1;
+            if in_degree[to] == 0 {
+                ready.push(to);
+            }
+        }
+    }
+    if visited < leads_to_normal_form.iter().filter(|&&b| b).count() {
+        graph.longest_unbounded = true;
+        return;
+    }
+    let target = (0..n)
+        .filter(|&i| graph.nodes[i].normal_form)
+        .filter_map(|i| Some((distance[i]?, i)))
+        .max();
+    if let Some((_, target)) = target {
+        mark(&mut graph.edges, &predecessor, target, |edge| {
+            edge.longest = true
+        });
+    }
+}
+
+/// Write the graph in Graphviz DOT. Normal forms are drawn with a double
+/// border and partly explored nodes dashed; the shortest path to a normal
+/// form is blue and the longest red.
+pub fn to_dot(graph: &Graph, options: PrintOptions) -> String {
+    let mut out = String::from("digraph reductions {\n");
+    out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let mut attributes = vec![format!(
+            "label=\"{}\"",
+            escape(&ast_to_string(&node.term, options))
+        )];
+        if node.normal_form {
+            attributes.push("peripheries=2".to_string());
+        }
+        if !node.expanded {
+            attributes.push("style=dashed".to_string());
+        }
+        out.push_str(&format!("    n{} [{}];\n", i, attributes.join(", ")));
+    }
+    for edge in &graph.edges {
+        let label = match edge.kind {
+            StepKind::Beta => "β",
+            StepKind::Eta => "η",
+            StepKind::Let => "let",
+        };
+        let mut attributes = vec![format!("label=\"{}\"", label)];
+        match (edge.shortest, edge.longest) {
+            (true, true) => attributes.push("color=\"blue:red\", penwidth=2".to_string()),
+            (true, false) => attributes.push("color=blue, penwidth=2".to_string()),
+            (false, true) => attributes.push("color=red, penwidth=2".to_string()),
+            (false, false) => {}
+        }
+        out.push_str(&format!(
+            "    n{} -> n{} [{}];\n",
+            edge.from,
+            edge.to,
+            attributes.join(", ")
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions};
+
+    fn explore_default(input: &str, max_nodes: usize) -> Graph {
+        let ast = parse(input, ParseOptions::default()).expect("the term parses");
+        explore(&ast, Reduction::Beta, max_nodes, 100)
+    }
+
+    fn print(node: &Node) -> String {
+        ast_to_string(&node.term, PrintOptions::default())
+    }
+
+    #[test]
+    fn shortest_and_longest_paths() {
+        let graph = explore_default("(λx.y) ((λz.z) w)", 100);
+        let nodes: Vec<_> = graph
+            .nodes
+            .iter()
+            .map(|node| (print(node), node.depth, node.normal_form))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                ("(λx.y) ((λz.z) w)".to_string(), 0, false),
+                ("y".to_string(), 1, true),
+                ("(λx.y) w".to_string(), 1, false),
+            ]
+        );
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.shortest, edge.longest))
+            .collect();
+        assert_eq!(
+            edges,
+            [
+                (0, 1, true, false),
+                (0, 2, false, true),
+                (2, 1, false, true)
+            ]
+        );
+        assert!(graph.complete && !graph.longest_unbounded);
+
+        let dot = to_dot(&graph, PrintOptions::default());
+        assert!(dot.contains("n1 [label=\"y\", peripheries=2];"));
+        assert!(dot.contains("n0 -> n1 [label=\"β\", color=blue, penwidth=2];"));
+        assert!(dot.contains("n0 -> n2 [label=\"β\", color=red, penwidth=2];"));
+    }
+
+    #[test]
+    fn cycles_and_limits() {
+        // Ω reduces to itself, so its graph is one node with a loop.
+        let graph = explore_default("(λx.x x) (λx.x x)", 100);
+        assert_eq!(graph.nodes.len(), 1);
+        assert_eq!((graph.edges[0].from, graph.edges[0].to), (0, 0));
+        assert!(graph.complete && !graph.nodes[0].normal_form);
+
+        // `y` can be reached after going round Ω any number of times.
+        let graph = explore_default("(λx.y) ((λx.x x) (λx.x x))", 100);
+        assert!(graph.longest_unbounded);
+        assert!(graph.edges.iter().any(|edge| edge.shortest));
+        assert!(!graph.edges.iter().any(|edge| edge.longest));
+
+        // Only the root fits, so it is not fully explored.
+        let graph = explore_default("(λx.y) ((λz.z) w)", 1);
+        assert_eq!(graph.nodes.len(), 1);
+        assert!(!graph.complete && !graph.nodes[0].expanded);
+    }
+}
@@ -1,31 +1,94 @@
-use wasm_bindgen::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+
+mod alpha;
+/// Native entry points for the benchmarks in `benches/`.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod development;
+mod dialect;
+mod env;
+mod graph;
+mod machine;
+mod nameless;
+mod need;
+mod normalize;
+mod numerals;
+mod pretty;
+mod program;
+mod redex;
+mod strategy;
+#[cfg(test)]
+mod tests;
+mod traverse;
+
+use dialect::Dialect;
+use nameless::Term;
+use numerals::Encoding;
+use strategy::{beta_reduce, Branch, Reduction, Strategy};
+use traverse::{Children, Fold, Scope};
 
-#[derive(Clone, Debug)]
+// `Clone`, `PartialEq`, `Hash` and `Drop` are implemented in `traverse`
+// without recursion.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
 enum AST {
     Var(String),
     App(Box<AST>, Box<AST>),
-    Lambda { param: String, body: Box<AST> },
+    Lambda {
+        param: String,
+        body: Box<AST>,
+    },
+    // `let name = value in body`, kept only when `ParseOptions::keep_let` is
+    // set. `fixpoint` is `Some` for `letrec`, where `name` is also in scope
+    // in `value`.
+    Let {
+        name: String,
+        value: Box<AST>,
+        body: Box<AST>,
+        fixpoint: Option<Fixpoint>,
+    },
+    // Placeholder for a term the recovering parser could not read.
+    Error,
 }
 
 fn free_vars(ast: &AST) -> HashSet<String> {
-    match ast {
-        AST::Var(name) => {
-            let mut set = HashSet::new();
-            set.insert(name.clone());
-            set
+    struct Free<'a> {
+        scope: Scope<'a>,
+        free: HashSet<String>,
+    }
+    impl<'a> Fold<'a, AST> for Free<'a> {
+        type Output = ();
+        fn visit(&mut self, node: &'a AST) -> Option<()> {
+            match node {
+                AST::Var(name) if self.scope.get(name).is_none() => {
+                    self.free.insert(name.clone());
+                }
+                _ => {}
+            }
+            None
         }
-        AST::Lambda { param, body } => {
-            let mut set = free_vars(body);
-            set.remove(param);
-            set
+        fn descend(&mut self, node: &'a AST, branch: Branch) {
+            if let Some(name) = traverse::binder(node, branch) {
+                self.scope.bind(name, ());
+            }
         }
-        AST::App(left, right) => {
-            let mut set = free_vars(left);
-            set.extend(free_vars(right));
-            set
+        fn ascend(&mut self, node: &'a AST, branch: Branch) {
+            if let Some(name) = traverse::binder(node, branch) {
+                self.scope.unbind(name);
+            }
         }
+        fn build(&mut self, _node: &'a AST, _children: Children<()>) {}
     }
+    let mut folder = Free {
+        scope: Scope::new(),
+        free: HashSet::new(),
+    };
+    traverse::fold(ast, &mut folder);
+    folder.free
 }
 
 fn fresh_var(existing: &HashSet<String>, base: &str) -> String {
@@ -38,232 +101,1952 @@ fn fresh_var(existing: &HashSet<String>, base: &str) -> String {
     new_var
 }
 
-fn substitute(ast: &AST, variable: &str, replacement: &AST) -> AST {
-    match ast {
-        AST::Var(name) => {
-            if name == variable {
-                replacement.clone()
-            } else {
-                ast.clone()
-            }
-        }
-        AST::App(left, right) => AST::App(
-            Box::new(substitute(left, variable, replacement)),
-            Box::new(substitute(right, variable, replacement)),
-        ),
-        AST::Lambda { param, body } => {
-            if param == variable {
-                ast.clone()
-            } else {
-                let replacement_free = free_vars(replacement);
-                if replacement_free.contains(param) {
-                    let body_free = free_vars(body);
-                    let all_free: HashSet<String> =
-                        replacement_free.union(&body_free).cloned().collect();
-                    let new_param = fresh_var(&all_free, param);
-                    let renamed_body = substitute(body, param, &AST::Var(new_param.clone()));
-                    AST::Lambda {
-                        param: new_param,
-                        body: Box::new(substitute(&renamed_body, variable, replacement)),
-                    }
-                } else {
-                    AST::Lambda {
-                        param: param.clone(),
-                        body: Box::new(substitute(body, variable, replacement)),
-                    }
-                }
-            }
-        }
+/// A binder renamed when a reduct was read back into named form, so that it
+/// does not capture a variable of the same name.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+/// A half-open range of character offsets into the source text.
+///
+/// Offsets count Unicode scalar values (Rust `char`s) rather than bytes, so
+/// `λ` occupies a single position. They are not UTF-16 code units either,
+/// which is what JavaScript strings and most editors index by: every
+/// character outside the Basic Multilingual Plane before a position, such as
+/// `𝑥` or an emoji, puts its UTF-16 offset one further along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum Token {
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum Token {
     Lambda,
     Dot,
+    Arrow,
+    Comma,
+    Equals,
+    Semicolon,
+    Let,
+    LetRec,
+    In,
     LParen,
     RParen,
+    Number(u64),
     Identifier(String),
 }
 
-fn tokenize(input: &str) -> Vec<Token> {
+impl Token {
+    fn kind(&self) -> TokenKind {
+        match self {
+            Token::Lambda => TokenKind::Lambda,
+            Token::Dot => TokenKind::Dot,
+            Token::Arrow => TokenKind::Arrow,
+            Token::Comma => TokenKind::Comma,
+            Token::Equals => TokenKind::Equals,
+            Token::Semicolon => TokenKind::Semicolon,
+            Token::Let => TokenKind::Let,
+            Token::LetRec => TokenKind::LetRec,
+            Token::In => TokenKind::In,
+            Token::LParen => TokenKind::LParen,
+            Token::RParen => TokenKind::RParen,
+            Token::Number(_) => TokenKind::Number,
+            Token::Identifier(_) => TokenKind::Identifier,
+        }
+    }
+}
+
+/// The class of a token, used to describe what the parser expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TokenKind {
+    Lambda,
+    Dot,
+    Arrow,
+    Comma,
+    Equals,
+    Semicolon,
+    Let,
+    LetRec,
+    In,
+    LParen,
+    RParen,
+    Number,
+    Identifier,
+}
+
+/// A parse failure, pointing at the offending token.
+///
+/// `found` is `None` when the input ended early, in which case `span` is the
+/// empty range just past the last token, or when the offending text is not a
+/// token at all (a lexical error).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    pub found: Option<Token>,
+    pub expected: Vec<TokenKind>,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Options controlling how source text is read.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct ParseOptions {
+    /// Reject unknown characters and tokens left over after the expression.
+    /// With `strict: false` both are silently ignored, as in earlier versions.
+    pub strict: bool,
+    /// Keep `let` and `letrec` as nodes of their own instead of desugaring
+    /// them while parsing. Expanding one is then a reduction step.
+    pub keep_let: bool,
+    /// The fixed-point combinator `letrec` desugars to. When unset, `Y`, or
+    /// whichever one suits the chosen `Strategy`.
+    pub fixpoint: Option<Fixpoint>,
+    /// The numerals that numeric literals such as `3` desugar to.
+    pub encoding: Encoding,
+    /// The surface syntax of the input.
+    pub dialect: Dialect,
+    /// How deeply abstractions, `let`s and parentheses may nest. Deeper
+    /// input is rejected with an error rather than using unbounded memory.
+    pub max_nesting: usize,
+}
+
+/// The default for `ParseOptions::max_nesting`.
+pub const MAX_NESTING: usize = 1_000_000;
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strict: true,
+            keep_let: false,
+            fixpoint: None,
+            encoding: Encoding::default(),
+            dialect: Dialect::default(),
+            max_nesting: MAX_NESTING,
+        }
+    }
+}
+
+/// A fixed-point combinator for desugaring `letrec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+pub enum Fixpoint {
+    /// `λf.(λx.f (x x)) (λx.f (x x))`, for normal-order reduction.
+    #[default]
+    Y,
+    /// `λf.(λx.f (λv.x x v)) (λx.f (λv.x x v))`, which also works under
+    /// call-by-value because the self-application is guarded by a lambda.
+    Z,
+}
+
+impl Fixpoint {
+    fn source(self) -> &'static str {
+        match self {
+            Fixpoint::Y => "λf.(λx.f (x x)) (λx.f (x x))",
+            Fixpoint::Z => "λf.(λx.f (λv.x x v)) (λx.f (λv.x x v))",
+        }
+    }
+
+    fn term(self) -> AST {
+        parse(self.source(), ParseOptions::default()).expect("fixed-point combinator parses")
+    }
+}
+
+/// A comment, kept out of the token stream but recorded with its location.
+/// `text` excludes the `--` or `{- -}` delimiters and surrounding spaces.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+struct Lexed {
+    tokens: Vec<(Token, Span)>,
+    comments: Vec<Comment>,
+    // One error for every unknown character. The characters themselves are
+    // skipped, so lenient callers can ignore these.
+    errors: Vec<ParseError>,
+}
+
+fn tokenize(input: &str, dialect: Dialect) -> Lexed {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
-    while let Some(&c) = chars.peek() {
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
+    let mut chars = input.chars().enumerate().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let following = chars.clone().nth(1).map(|(_, ch)| ch);
         if c.is_whitespace() {
             chars.next();
-        } else if c == '(' {
-            tokens.push(Token::LParen);
+        } else if dialect.starts_line_comment(c, following) {
+            // Line comment: runs to the end of the line.
+            let mut text = String::new();
+            let mut end = start;
+            for (i, ch) in chars.by_ref() {
+                if ch == '\n' {
+                    break;
+                }
+                text.push(ch);
+                end = i + 1;
+            }
+            comments.push(Comment {
+                text: text.trim_start_matches(c).trim().to_string(),
+                span: Span::new(start, end),
+            });
+        } else if c == '{' && following == Some('-') && dialect.has_block_comments() {
+            // Block comment: `{- ... -}`, which may nest.
+            let mut text = String::new();
+            let mut end = start;
+            let mut depth = 0;
+            let mut previous = None;
+            for (i, ch) in chars.by_ref() {
+                text.push(ch);
+                end = i + 1;
+                match (previous, ch) {
+                    (Some('{'), '-') => {
+                        depth += 1;
+                        previous = None;
+                        continue;
+                    }
+                    (Some('-'), '}') => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                        previous = None;
+                        continue;
+                    }
+                    _ => {}
+                }
+                previous = Some(ch);
+            }
+            if depth > 0 {
+                errors.push(ParseError {
+                    message: "Unterminated block comment".to_string(),
+                    span: Span::new(start, end),
+                    found: None,
+                    expected: Vec::new(),
+                });
+            } else {
+                let inner = &text[2..text.len() - 2];
+                comments.push(Comment {
+                    text: inner.trim().to_string(),
+                    span: Span::new(start, end),
+                });
+            }
+        } else if c == '(' || (c == '{' && dialect == Dialect::Latex) {
+            tokens.push((Token::LParen, Span::new(start, start + 1)));
+            chars.next();
+        } else if c == ')' || (c == '}' && dialect == Dialect::Latex) {
+            tokens.push((Token::RParen, Span::new(start, start + 1)));
+            chars.next();
+        } else if c == '-' && following == Some('>') && dialect == Dialect::Haskell {
+            tokens.push((Token::Arrow, Span::new(start, start + 2)));
+            chars.next();
             chars.next();
-        } else if c == ')' {
-            tokens.push(Token::RParen);
+        } else if c == '\\' && dialect == Dialect::Latex {
+            // A command: `\lambda`, or one of the spacing commands.
             chars.next();
+            let mut name = String::new();
+            let mut end = start + 1;
+            while let Some(&(i, ch)) = chars.peek() {
+                if ch.is_ascii_alphabetic() || (name.is_empty() && !ch.is_alphanumeric()) {
+                    name.push(ch);
+                    end = i + 1;
+                    chars.next();
+                    if !ch.is_ascii_alphabetic() {
+                        break;
+                    }
+                } else {
+                    break;
+                }
+            }
+            if name == "lambda" {
+                tokens.push((Token::Lambda, Span::new(start, end)));
+            } else if !dialect::LATEX_SPACING.contains(&name.as_str()) {
+                errors.push(ParseError {
+                    message: format!("Unknown command '\\{}'", name),
+                    span: Span::new(start, end),
+                    found: None,
+                    expected: Vec::new(),
+                });
+            }
         } else if c == '.' {
-            tokens.push(Token::Dot);
+            tokens.push((Token::Dot, Span::new(start, start + 1)));
+            chars.next();
+        } else if c == ',' {
+            tokens.push((Token::Comma, Span::new(start, start + 1)));
+            chars.next();
+        } else if c == '=' {
+            tokens.push((Token::Equals, Span::new(start, start + 1)));
+            chars.next();
+        } else if c == ';' {
+            tokens.push((Token::Semicolon, Span::new(start, start + 1)));
             chars.next();
         } else if c == '\\' || c == 'λ' {
-            tokens.push(Token::Lambda);
+            tokens.push((Token::Lambda, Span::new(start, start + 1)));
             chars.next();
         } else if c.is_alphanumeric() || c == '_' {
             let mut ident = String::new();
-            while let Some(&ch) = chars.peek() {
+            let mut end = start;
+            while let Some(&(i, ch)) = chars.peek() {
                 if ch.is_alphanumeric() || ch == '_' {
                     ident.push(ch);
+                    end = i + 1;
                     chars.next();
                 } else {
                     break;
                 }
             }
-            tokens.push(Token::Identifier(ident));
+            let token = match ident.as_str() {
+                "lambda" if dialect == Dialect::Lisp => Token::Lambda,
+                "let" => Token::Let,
+                "letrec" => Token::LetRec,
+                "in" => Token::In,
+                _ if ident.chars().all(|ch| ch.is_ascii_digit()) => {
+                    // Saturate so that oversized literals are still reported
+                    // as too large by the parser.
+                    Token::Number(ident.parse().unwrap_or(u64::MAX))
+                }
+                _ => Token::Identifier(ident),
+            };
+            tokens.push((token, Span::new(start, end)));
         } else {
-            // Skip any unknown characters.
+            errors.push(ParseError {
+                message: format!("Unexpected character '{}'", c),
+                span: Span::new(start, start + 1),
+                found: None,
+                expected: Vec::new(),
+            });
             chars.next();
         }
     }
-    tokens
+    Lexed {
+        tokens,
+        comments,
+        errors,
+    }
+}
+
+/// Where the nodes of a parsed term came from, mirroring the term's shape:
+/// an application has its function and argument as children, an
+/// abstraction its body, and a `let` its value and body. Nodes that were not
+/// written out in the source, such as the insides of a numeral, have no
+/// children.
+#[derive(Debug)]
+struct SpanTree {
+    span: Span,
+    children: Vec<SpanTree>,
+}
+
+impl Drop for SpanTree {
+    // Dropping recursively could overflow the stack on a deep term.
+    fn drop(&mut self) {
+        let mut orphans = std::mem::take(&mut self.children);
+        while let Some(mut tree) = orphans.pop() {
+            orphans.append(&mut tree.children);
+        }
+    }
+}
+
+impl SpanTree {
+    // The span of the node at the end of `path`, if it is known.
+    fn find(&self, path: &[Branch]) -> Option<Span> {
+        let mut tree = self;
+        for branch in path {
+            let index = match branch {
+                Branch::Function | Branch::Value => 0,
+                Branch::Argument => 1,
+                // An abstraction's only child, or the second of a `let`'s.
+                Branch::Body => tree.children.len().saturating_sub(1),
+            };
+            tree = tree.children.get(index)?;
+        }
+        Some(tree.span)
+    }
+
+    // Reshape the tree of a `let` to match what `expand_let` turns it into.
+    fn expand_let(&mut self, recursive: bool) {
+        let body = self.children.pop().expect("let has a body");
+        let mut value = self.children.pop().expect("let has a value");
+        if recursive {
+            // `FIX (λname.value)`, where the combinator has no source.
+            let span = value.span;
+            let node = |children| SpanTree { span, children };
+            value = node(vec![node(Vec::new()), node(vec![value])]);
+        }
+        let abstraction = SpanTree {
+            span: self.span,
+            children: vec![body],
+        };
+        self.children = vec![abstraction, value];
+    }
 }
 
 struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<(Token, Span)>,
     pos: usize,
+    options: ParseOptions,
+    // When set, errors are collected in `diagnostics` instead of aborting.
+    recover: bool,
+    diagnostics: Vec<ParseError>,
+    // Number of '(' currently open, so recovery knows whether a ')' is stray.
+    depth: usize,
+    // When recording, one tree for every term parsed so far whose node has
+    // not yet become part of a larger one, innermost last.
+    spans: Option<Vec<SpanTree>>,
 }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+    fn new(tokens: Vec<(Token, Span)>, options: ParseOptions) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            options,
+            recover: false,
+            diagnostics: Vec::new(),
+            depth: 0,
+            spans: None,
+        }
+    }
+
+    fn recording_spans(tokens: Vec<(Token, Span)>, options: ParseOptions) -> Self {
+        Parser {
+            spans: Some(Vec::new()),
+            ..Parser::new(tokens, options)
+        }
+    }
+
+    fn recovering(tokens: Vec<(Token, Span)>, options: ParseOptions) -> Self {
+        Parser {
+            recover: true,
+            ..Parser::new(tokens, options)
+        }
     }
 
     // Peek returns a reference to the next token.
     fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+        self.tokens.get(self.pos).map(|(tok, _)| tok)
     }
 
     // next returns an owned token (cloned) so that no mutable borrow lingers.
     fn next(&mut self) -> Option<Token> {
-        let tok = self.tokens.get(self.pos).cloned();
+        let tok = self.peek().cloned();
         self.pos += 1;
         tok
     }
 
-    // Parse a factor: variable, lambda abstraction, or a parenthesized expression.
-    fn parse_factor(&mut self) -> Result<AST, String> {
-        let token = self.next();
-        match token {
-            Some(Token::Identifier(name)) => Ok(AST::Var(name)),
-            Some(Token::Lambda) => {
-                let param_token = self.next();
-                if let Some(Token::Identifier(param)) = param_token {
-                    let dot_token = self.next();
-                    if let Some(Token::Dot) = dot_token {
-                        let body = self.parse_application()?;
-                        Ok(AST::Lambda {
-                            param,
-                            body: Box::new(body),
-                        })
-                    } else {
-                        Err("Expected '.' after lambda parameter".into())
-                    }
-                } else {
-                    Err("Expected identifier after lambda".into())
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(tok, _)| tok)
+    }
+
+    fn at_factor_start(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                Token::Identifier(_)
+                    | Token::Number(_)
+                    | Token::Lambda
+                    | Token::LParen
+                    | Token::Let
+                    | Token::LetRec
+            )
+        )
+    }
+
+    // The span of the token at `pos`, or the empty span at the end of input.
+    fn span_at(&self, pos: usize) -> Span {
+        match self.tokens.get(pos) {
+            Some((_, span)) => *span,
+            None => {
+                let end = self.tokens.last().map_or(0, |(_, span)| span.end);
+                Span::new(end, end)
+            }
+        }
+    }
+
+    // When recording spans, note that the node just built starts with the
+    // token at `start` and ends with the one just consumed, and that its
+    // children are the last `children` nodes noted.
+    fn located(&mut self, start: usize, children: usize) {
+        let begin = self.span_at(start).start;
+        let end = if self.pos > start {
+            self.span_at(self.pos - 1).end
+        } else {
+            begin
+        };
+        if let Some(spans) = &mut self.spans {
+            let children = spans.split_off(spans.len() - children);
+            spans.push(SpanTree {
+                span: Span::new(begin, end),
+                children,
+            });
+        }
+    }
+
+    // Build an error for the token that is about to be consumed.
+    fn error(&self, message: &str, expected: &[TokenKind]) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            span: self.span_at(self.pos),
+            found: self.peek().cloned(),
+            expected: expected.to_vec(),
+        }
+    }
+
+    // Record the error when recovering, otherwise propagate it.
+    fn report(&mut self, error: ParseError) -> Result<(), ParseError> {
+        if self.recover {
+            self.diagnostics.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    // Skip ahead to the next synchronisation point: ')', '.' (or '->') or end
+    // of input.
+    fn synchronize(&mut self) {
+        while !matches!(
+            self.peek(),
+            None | Some(Token::RParen | Token::Dot | Token::Arrow)
+        ) {
+            self.pos += 1;
+        }
+    }
+
+    // Refuse to go `nesting` levels deep if that is more than the options
+    // allow. This is not a syntax error, so it is never recovered from.
+    fn check_nesting(&self, nesting: usize) -> Result<(), ParseError> {
+        if nesting < self.options.max_nesting {
+            return Ok(());
+        }
+        let message = format!(
+            "Term nested too deeply (at most {} levels)",
+            self.options.max_nesting
+        );
+        Err(self.error(&message, &[]))
+    }
+
+    // Parse a variable or a numeral.
+    fn parse_atom(&mut self) -> Result<AST, ParseError> {
+        let start = self.pos;
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                let encoding = self.options.encoding;
+                if n > encoding.limit() {
+                    let message = format!("Numeral too large (at most {})", encoding.limit());
+                    self.report(self.error(&message, &[]))?;
+                    self.next();
+                    self.located(start, 0);
+                    return Ok(AST::Error);
                 }
+                self.next();
+                self.located(start, 0);
+                Ok(numerals::encode(n, encoding))
+            }
+            Some(Token::Identifier(name)) => {
+                self.next();
+                self.located(start, 0);
+                Ok(AST::Var(name))
+            }
+            _ => unreachable!("parse_atom called on an atom"),
+        }
+    }
+
+    // Parse a factor: variable, lambda abstraction, or a parenthesized
+    // expression. A factor with a term inside it is only begun, by pushing
+    // what remains to be done onto `pending`, and `None` is returned.
+    fn parse_factor(&mut self, pending: &mut Vec<Pending>) -> Result<Option<AST>, ParseError> {
+        let start = self.pos;
+        match self.peek().cloned() {
+            Some(Token::Identifier(_) | Token::Number(_)) => self.parse_atom().map(Some),
+            Some(Token::Lambda) => {
+                self.next();
+                self.parse_lambda(pending)
+            }
+            Some(Token::Let) => {
+                self.next();
+                self.parse_let(None, pending)
+            }
+            Some(Token::LetRec) => {
+                self.next();
+                self.parse_let(Some(self.options.fixpoint.unwrap_or_default()), pending)
             }
             Some(Token::LParen) => {
-                let expr = self.parse_application()?;
-                let closing_token = self.next();
-                if let Some(Token::RParen) = closing_token {
-                    Ok(expr)
-                } else {
-                    Err("Expected ')'".into())
+                self.next();
+                self.depth += 1;
+                self.open(pending, Pending::Group)?;
+                Ok(None)
+            }
+            Some(tok) => {
+                let message = format!("Unexpected token: {:?}", tok);
+                self.report(self.error(&message, FACTOR_START))?;
+                // A ')' is left for the enclosing group to close, if any, and
+                // an 'in' for the enclosing let.
+                if tok != Token::In && (tok != Token::RParen || self.depth == 0) {
+                    self.next();
                 }
+                self.located(start, 0);
+                Ok(Some(AST::Error))
+            }
+            None => {
+                self.report(self.error("Unexpected end of input", FACTOR_START))?;
+                self.located(start, 0);
+                Ok(Some(AST::Error))
             }
-            Some(tok) => Err(format!("Unexpected token: {:?}", tok)),
-            None => Err("Unexpected end of input".into()),
         }
     }
 
-    // Parse an application (left-associative).
-    fn parse_application(&mut self) -> Result<AST, String> {
-        let mut expr = self.parse_factor()?;
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Identifier(_) | Token::Lambda | Token::LParen => {
-                    let next_factor = self.parse_factor()?;
-                    expr = AST::App(Box::new(expr), Box::new(next_factor));
+    // Wait for the term starting at the next token, to finish `frame` with.
+    fn open(&self, pending: &mut Vec<Pending>, frame: Pending) -> Result<(), ParseError> {
+        // Every level of nesting adds two frames to the one for the whole term.
+        self.check_nesting(pending.len() / 2)?;
+        pending.push(frame);
+        pending.push(Pending::Application {
+            start: self.pos,
+            function: None,
+        });
+        Ok(())
+    }
+
+    // Expect the ')' closing a group.
+    fn close_paren(&mut self) -> Result<(), ParseError> {
+        if let Some(Token::RParen) = self.peek() {
+            self.next();
+        } else {
+            self.report(self.error("Expected ')'", &[TokenKind::RParen]))?;
+            // Drop everything up to and including the matching ')'.
+            while let Some(tok) = self.next() {
+                if tok == Token::RParen {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Parse the rest of a lambda abstraction after the 'λ', up to its body.
+    // Several binders, optionally separated by commas, abbreviate nested
+    // abstractions: `λf x.M` and `λf,x.M` both mean `λf.λx.M`.
+    fn parse_lambda(&mut self, pending: &mut Vec<Pending>) -> Result<Option<AST>, ParseError> {
+        // Where each of the nested abstractions starts.
+        let mut starts = vec![self.pos - 1];
+        let mut params = Vec::new();
+        while let Some(Token::Identifier(param)) = self.peek() {
+            if !params.is_empty() {
+                starts.push(self.pos);
+            }
+            params.push(param.clone());
+            self.next();
+            if self.peek() == Some(&Token::Comma) {
+                self.next();
+                if !matches!(self.peek(), Some(Token::Identifier(_))) {
+                    self.report(
+                        self.error("Expected identifier after ','", &[TokenKind::Identifier]),
+                    )?;
+                }
+            }
+        }
+        let separator = self.options.dialect.binder_separator();
+        if params.is_empty() {
+            self.report(self.error("Expected identifier after lambda", &[TokenKind::Identifier]))?;
+            self.synchronize();
+            if self.peek() != Some(&separator) {
+                self.located(self.pos, 0);
+                return Ok(Some(self.located_lambdas(
+                    vec![ERROR_NAME.to_string()],
+                    &starts,
+                    AST::Error,
+                )));
+            }
+            params.push(ERROR_NAME.to_string());
+        }
+        if self.peek() == Some(&separator) {
+            self.next();
+        } else {
+            let message = match separator {
+                Token::Arrow => "Expected '->' after lambda parameter",
+                _ => "Expected '.' after lambda parameter",
+            };
+            self.report(self.error(
+                message,
+                &[separator.kind(), TokenKind::Identifier, TokenKind::Comma],
+            ))?;
+            // Treat the '.' as missing if a body follows; otherwise the body
+            // is missing too, but that is the same mistake.
+            if !self.at_factor_start() {
+                self.located(self.pos, 0);
+                return Ok(Some(self.located_lambdas(params, &starts, AST::Error)));
+            }
+        }
+        self.open(pending, Pending::Abstraction { params, starts })?;
+        Ok(None)
+    }
+
+    // `lambdas`, noting that the abstraction for each parameter begins at
+    // the matching position in `starts` and that the body was noted last.
+    fn located_lambdas(&mut self, params: Vec<String>, starts: &[usize], body: AST) -> AST {
+        for &start in starts.iter().rev() {
+            self.located(start, 1);
+        }
+        lambdas(params, body)
+    }
+
+    // Parse the rest of `let name = ` after the keyword, up to the value.
+    fn parse_let(
+        &mut self,
+        fixpoint: Option<Fixpoint>,
+        pending: &mut Vec<Pending>,
+    ) -> Result<Option<AST>, ParseError> {
+        let start = self.pos - 1;
+        let name = if let Some(Token::Identifier(name)) = self.peek() {
+            let name = name.clone();
+            self.next();
+            name
+        } else {
+            self.report(self.error("Expected identifier after let", &[TokenKind::Identifier]))?;
+            ERROR_NAME.to_string()
+        };
+        if self.peek() == Some(&Token::Equals) {
+            self.next();
+        } else {
+            self.report(self.error("Expected '=' in let", &[TokenKind::Equals]))?;
+        }
+        self.open(
+            pending,
+            Pending::LetValue {
+                start,
+                name,
+                fixpoint,
+            },
+        )?;
+        Ok(None)
+    }
+
+    fn make_let(
+        &mut self,
+        start: usize,
+        name: String,
+        value: AST,
+        body: AST,
+        fixpoint: Option<Fixpoint>,
+    ) -> AST {
+        self.located(start, 2);
+        if self.options.keep_let {
+            AST::Let {
+                name,
+                value: Box::new(value),
+                body: Box::new(body),
+                fixpoint,
+            }
+        } else {
+            if let Some(tree) = self.spans.as_mut().and_then(|spans| spans.last_mut()) {
+                tree.expand_let(fixpoint.is_some());
+            }
+            expand_let(name, value, body, fixpoint)
+        }
+    }
+
+    // Parse a whole term in the dialect being read.
+    fn parse_term(&mut self) -> Result<AST, ParseError> {
+        if self.options.dialect == Dialect::Lisp {
+            self.parse_sexpr()
+        } else {
+            self.parse_application()
+        }
+    }
+
+    // Parse an application (left-associative), with everything nested
+    // inside it.
+    fn parse_application(&mut self) -> Result<AST, ParseError> {
+        let mut pending = vec![Pending::Application {
+            start: self.pos,
+            function: None,
+        }];
+        loop {
+            let Some(mut term) = self.parse_factor(&mut pending)? else {
+                continue;
+            };
+            // Hand the finished term to the innermost pending one, and so on
+            // outwards for as long as that finishes it too.
+            loop {
+                let Some(frame) = pending.pop() else {
+                    return Ok(term);
+                };
+                match frame {
+                    Pending::Application { start, function } => {
+                        if let Some(function) = function {
+                            term = AST::App(Box::new(function), Box::new(term));
+                            self.located(start, 2);
+                        }
+                        if self.at_factor_start() {
+                            pending.push(Pending::Application {
+                                start,
+                                function: Some(term),
+                            });
+                            break;
+                        }
+                    }
+                    Pending::Group => {
+                        self.depth -= 1;
+                        self.close_paren()?;
+                    }
+                    Pending::Abstraction { params, starts } => {
+                        term = self.located_lambdas(params, &starts, term);
+                    }
+                    Pending::LetValue {
+                        start,
+                        name,
+                        fixpoint,
+                    } => {
+                        if self.peek() == Some(&Token::In) {
+                            self.next();
+                        } else {
+                            self.report(
+                                self.error("Expected 'in' after let binding", &[TokenKind::In]),
+                            )?;
+                            if !self.at_factor_start() {
+                                self.located(self.pos, 0);
+                                term = self.make_let(start, name, term, AST::Error, fixpoint);
+                                continue;
+                            }
+                        }
+                        pending.push(Pending::LetBody {
+                            start,
+                            name,
+                            value: term,
+                            fixpoint,
+                        });
+                        pending.push(Pending::Application {
+                            start: self.pos,
+                            function: None,
+                        });
+                        break;
+                    }
+                    Pending::LetBody {
+                        start,
+                        name,
+                        value,
+                        fixpoint,
+                    } => {
+                        term = self.make_let(start, name, value, term, fixpoint);
+                    }
                 }
-                _ => break,
             }
         }
-        Ok(expr)
     }
 }
 
-fn parse(tokens: Vec<Token>) -> Result<AST, String> {
-    let mut parser = Parser::new(tokens);
-    parser.parse_application()
+// What remains to be done with a term the parser has begun once the term
+// nested inside it has been parsed. The parser keeps these on a stack of its
+// own rather than recursing, so that it can read terms of any depth.
+enum Pending {
+    // An application beginning with the token at `start`, with the factors
+    // read so far already applied to each other.
+    Application {
+        start: usize,
+        function: Option<AST>,
+    },
+    // A parenthesized term, waiting for its ')'.
+    Group,
+    // The body of `λparams.`
+    Abstraction {
+        params: Vec<String>,
+        starts: Vec<usize>,
+    },
+    // The value of a `let`, to be followed by `in` and the body.
+    LetValue {
+        start: usize,
+        name: String,
+        fixpoint: Option<Fixpoint>,
+    },
+    // The body of a `let`.
+    LetBody {
+        start: usize,
+        name: String,
+        value: AST,
+        fixpoint: Option<Fixpoint>,
+    },
 }
 
-fn beta_reduce(ast: &AST) -> (bool, AST) {
-    match ast {
-        AST::App(left, right) => {
-            if let AST::Lambda { param, body } = &**left {
-                // Redex: (λx. M) N  --> M[x := N]
-                let reduced_ast = substitute(body, param, right);
-                (true, reduced_ast)
-            } else {
-                let (reduced_left, new_left) = beta_reduce(left);
-                if reduced_left {
-                    (true, AST::App(Box::new(new_left), right.clone()))
-                } else {
-                    let (reduced_right, new_right) = beta_reduce(right);
-                    if reduced_right {
-                        (true, AST::App(left.clone(), Box::new(new_right)))
-                    } else {
-                        (false, ast.clone())
-                    }
+// Nest one abstraction per parameter around `body`, outermost first.
+fn lambdas(params: Vec<String>, body: AST) -> AST {
+    params
+        .into_iter()
+        .rev()
+        .fold(body, |body, param| AST::Lambda {
+            param,
+            body: Box::new(body),
+        })
+}
+
+// Desugar a `let` with these parts: `let x = e1 in e2` is `(λx.e2) e1`,
+// and `letrec f = e1 in e2` is `(λf.e2) (FIX (λf.e1))`.
+fn expand_let(name: String, value: AST, body: AST, fixpoint: Option<Fixpoint>) -> AST {
+    let value = match fixpoint {
+        Some(fixpoint) => AST::App(
+            Box::new(fixpoint.term()),
+            Box::new(lambdas(vec![name.clone()], value)),
+        ),
+        None => value,
+    };
+    AST::App(Box::new(lambdas(vec![name], body)), Box::new(value))
+}
+
+// `ast` with every `let` in it desugared.
+fn expand_lets(ast: &AST) -> AST {
+    struct Expander;
+
+    impl<'a> Fold<'a, AST> for Expander {
+        type Output = AST;
+
+        fn build(&mut self, node: &'a AST, children: Children<AST>) -> AST {
+            match (node, children) {
+                (AST::Let { name, fixpoint, .. }, Children::Two(value, body)) => {
+                    expand_let(name.clone(), value, body, *fixpoint)
                 }
+                (node, children) => traverse::rebuild(node, children),
             }
         }
-        AST::Lambda { param, body } => {
-            let (reduced_body, new_body) = beta_reduce(body);
-            if reduced_body {
-                (true, AST::Lambda {
-                    param: param.clone(),
-                    body: Box::new(new_body),
-                })
-            } else {
-                (false, ast.clone())
+    }
+
+    traverse::fold(ast, &mut Expander)
+}
+
+// Tokens that may begin a factor.
+const FACTOR_START: &[TokenKind] = &[
+    TokenKind::Identifier,
+    TokenKind::Number,
+    TokenKind::Lambda,
+    TokenKind::LParen,
+    TokenKind::Let,
+    TokenKind::LetRec,
+];
+
+// Printed in place of anything the recovering parser could not make sense of.
+// The tokenizer never produces it as an identifier, so it cannot be captured.
+const ERROR_NAME: &str = "?";
+
+const TRAILING_TOKEN: &str = "Unexpected token after end of expression";
+
+// Tokenize, failing on the first lexical error in strict mode.
+fn tokenize_strict(input: &str, options: ParseOptions) -> Result<Lexed, ParseError> {
+    let mut lexed = tokenize(input, options.dialect);
+    if options.strict && !lexed.errors.is_empty() {
+        return Err(lexed.errors.swap_remove(0));
+    }
+    Ok(lexed)
+}
+
+/// Parse a program (see the `program` module) and expand its definitions
+/// into the main term. A lone expression is a program without definitions.
+fn parse(input: &str, options: ParseOptions) -> Result<AST, ParseError> {
+    parse_located(input, options, false).map(|(ast, _)| ast)
+}
+
+// `parse`, also returning where the nodes of the main term came from when
+// `locate` is set. Expanding definitions keeps the shape of the main term,
+// so the tree still fits it above the names that were expanded.
+fn parse_located(
+    input: &str,
+    options: ParseOptions,
+    locate: bool,
+) -> Result<(AST, Option<SpanTree>), ParseError> {
+    let lexed = tokenize_strict(input, options)?;
+    let mut parser = if locate {
+        Parser::recording_spans(lexed.tokens, options)
+    } else {
+        Parser::new(lexed.tokens, options)
+    };
+    let program = parser.parse_program()?;
+    if options.strict && parser.peek().is_some() {
+        return Err(parser.error(TRAILING_TOKEN, &[]));
+    }
+    // The main term is parsed last.
+    let spans = parser.spans.and_then(|mut spans| spans.pop());
+    let (ast, errors) = program.resolve();
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok((ast, spans)),
+    }
+}
+
+/// Parse a program and pair each of its definitions with the comments that
+/// precede it, for formatters and documentation extractors.
+fn definition_comments_internal(
+    input: &str,
+    options: ParseOptions,
+) -> Result<Vec<program::DefinitionComments>, ParseError> {
+    let lexed = tokenize_strict(input, options)?;
+    let mut parser = Parser::new(lexed.tokens, options);
+    let program = parser.parse_program()?;
+    Ok(program.attach_comments(input, &lexed.comments))
+}
+
+// Reprint a program in another dialect, leaving its definitions unexpanded.
+fn convert_internal(input: &str, options: Options) -> Result<String, ParseError> {
+    let lexed = tokenize_strict(input, options.parse)?;
+    let mut parser = Parser::new(lexed.tokens, options.parse);
+    let program = parser.parse_program()?;
+    if options.parse.strict && parser.peek().is_some() {
+        return Err(parser.error(TRAILING_TOKEN, &[]));
+    }
+    Ok(dialect::print_program(&program, options.print))
+}
+
+/// Parse as much of the input as possible, returning a partial AST with
+/// `AST::Error` nodes where something was missing, together with every
+/// error found along the way, in source order.
+fn parse_recovering(input: &str, options: ParseOptions) -> (AST, Vec<ParseError>) {
+    let lexed = tokenize(input, options.dialect);
+    let mut parser = Parser::recovering(lexed.tokens, options);
+    // Only a term nested too deeply stops a recovering parser.
+    let mut program = match parser.parse_program() {
+        Ok(program) => program,
+        Err(error) => return (AST::Error, vec![error]),
+    };
+    let mut diagnostics = std::mem::take(&mut parser.diagnostics);
+    if options.strict {
+        diagnostics.extend(lexed.errors);
+        // Anything left over is a stray closing token; report it and go on.
+        while parser.peek().is_some() {
+            diagnostics.push(parser.error(TRAILING_TOKEN, &[]));
+            parser.next();
+            if parser.at_factor_start() {
+                let rest = match parser.parse_term() {
+                    Ok(rest) => rest,
+                    Err(error) => return (AST::Error, vec![error]),
+                };
+                let main = std::mem::replace(&mut program.main, AST::Error);
+                program.main = AST::App(Box::new(main), Box::new(rest));
+                diagnostics.append(&mut parser.diagnostics);
             }
         }
-        _ => (false, ast.clone()),
     }
+    let (expr, errors) = program.resolve();
+    diagnostics.extend(errors);
+    diagnostics.sort_by_key(|e| e.span.start);
+    (expr, diagnostics)
 }
 
-fn ast_to_string(ast: &AST) -> String {
-    match ast {
-        AST::Var(name) => name.clone(),
-        AST::Lambda { param, body } => format!("λ{}.{}", param, ast_to_string(body)),
-        AST::App(left, right) => {
-            let left_str = match **left {
-                AST::Lambda { .. } => format!("({})", ast_to_string(left)),
-                _ => ast_to_string(left),
-            };
-            let right_str = match **right {
-                AST::Var(_) => ast_to_string(right),
-                _ => format!("({})", ast_to_string(right)),
-            };
-            format!("{} {}", left_str, right_str)
+/// Options controlling how terms are printed.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct PrintOptions {
+    /// Fold directly nested abstractions into one binder list, printing
+    /// `λf.λx.f x` as `λf x.f x`.
+    pub compact_binders: bool,
+    /// Print numerals in this encoding as numbers, so that `λf.λx.f (f x)`
+    /// reads `2`. Usually the same as `ParseOptions::encoding`.
+    #[serde(rename = "print_numerals")]
+    pub numerals: Option<Encoding>,
+    /// The surface syntax to print in.
+    #[serde(rename = "print_dialect")]
+    pub dialect: Dialect,
+    /// Break lines so that output fits in this many columns where possible.
+    /// Without a width everything is printed on one line.
+    pub width: Option<usize>,
+    /// Write `\` instead of `λ` in the standard dialect.
+    pub ascii: bool,
+}
+
+fn ast_to_string(ast: &AST, options: PrintOptions) -> String {
+    if options.dialect == Dialect::Lisp {
+        return dialect::print_lisp(ast, options);
+    }
+    let width = options.width.unwrap_or(usize::MAX);
+    pretty::render(&pretty::term(ast, options), width)
+}
+
+/// Everything a caller can configure, read from a single flat JS object
+/// such as `{ strict: false, compact_binders: true }`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    #[serde(flatten)]
+    pub parse: ParseOptions,
+    #[serde(flatten)]
+    pub print: PrintOptions,
+    /// Which redex each reduction step contracts.
+    pub strategy: Strategy,
+    /// Whether steps may be η-reductions as well as β-reductions.
+    pub reduction: Reduction,
+}
+
+impl Options {
+    // The parse options, with `letrec` desugared to a fixed-point combinator
+    // that works under the reduction strategy unless one was chosen.
+    fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            fixpoint: Some(self.parse.fixpoint.unwrap_or(self.strategy.fixpoint())),
+            ..self.parse
+        }
+    }
+}
+
+fn normalize_internal(
+    input: &str,
+    max_steps: usize,
+    options: Options,
+) -> Result<NormalizeReport, ParseError> {
+    let ast = parse(input, options.parse_options())?;
+    let result = normalize::normalize(ast, options.strategy, options.reduction, max_steps);
+    Ok(NormalizeReport {
+        term: ast_to_string(&result.term, options.print),
+        steps: result.steps,
+        outcome: result.outcome,
+    })
+}
+
+fn trace_internal(
+    input: &str,
+    max_steps: usize,
+    options: Options,
+) -> Result<TraceReport, ParseError> {
+    let ast = parse(input, options.parse_options())?;
+    let mut steps = Vec::new();
+    let result = normalize::normalize_with(
+        ast,
+        options.strategy,
+        options.reduction,
+        max_steps,
+        |before, step| steps.push(TraceStep::new(before, step, options.print)),
+    );
+    Ok(TraceReport {
+        steps,
+        outcome: result.outcome,
+    })
+}
+
+fn redexes_internal(input: &str, options: Options) -> Result<Vec<RedexReport>, ParseError> {
+    let (ast, spans) = parse_located(input, options.parse_options(), true)?;
+    let redexes = redex::redexes(&ast, options.reduction)
+        .into_iter()
+        .map(|redex| RedexReport {
+            span: spans.as_ref().and_then(|spans| spans.find(&redex.path)),
+            path: redex.path,
+            kind: redex.kind,
+            variable: redex.variable,
+            argument: ast_to_string(&redex.argument, options.print),
+        })
+        .collect();
+    Ok(redexes)
+}
+
+fn reduction_graph_internal(
+    input: &str,
+    max_nodes: usize,
+    max_depth: usize,
+    options: Options,
+) -> Result<graph::Graph, ParseError> {
+    let ast = parse(input, options.parse_options())?;
+    Ok(graph::explore(
+        &ast,
+        options.reduction,
+        max_nodes,
+        max_depth,
+    ))
+}
+
+fn evaluate_by_need_internal(
+    input: &str,
+    max_steps: usize,
+    trace: bool,
+    options: Options,
+) -> Result<NeedReport, ParseError> {
+    let ast = parse(input, options.parse_options())?;
+    let result = need::evaluate(&ast, max_steps, trace);
+    let print = |ast: &AST| ast_to_string(ast, options.print);
+    Ok(NeedReport {
+        term: result.term.as_ref().map(print),
+        steps: result.steps,
+        outcome: result.outcome,
+        thunks: result
+            .thunks
+            .iter()
+            .map(|thunk| ThunkReport {
+                id: thunk.id,
+                term: thunk.term.as_ref().map(print),
+                forced: thunk.forced,
+                copies: thunk.copies,
+            })
+            .collect(),
+        trace: result
+            .trace
+            .iter()
+            .map(|entry| NeedStep {
+                rule: entry.rule,
+                control: print(&entry.control),
+                stack: entry.stack.clone(),
+                cell: entry.cell.as_ref().map(|(id, contents)| CellReport {
+                    id: *id,
+                    contents: print(contents),
+                }),
+            })
+            .collect(),
+    })
+}
+
+fn run_machine_internal(
+    input: &str,
+    machine: machine::Kind,
+    max_steps: usize,
+    trace: bool,
+    options: Options,
+) -> Result<MachineReport, ParseError> {
+    let ast = parse(input, options.parse_options())?;
+    let result = machine::run(&ast, machine, max_steps, trace);
+    Ok(MachineReport {
+        term: result
+            .term
+            .as_ref()
+            .map(|ast| ast_to_string(ast, options.print)),
+        steps: result.steps,
+        outcome: result.outcome,
+        trace: result
+            .trace
+            .iter()
+            .map(|entry| MachineStep::new(Some(entry.rule), &entry.state, options.print))
+            .collect(),
+    })
+}
+
+fn next_beta_reduction_internal(input: &str, options: Options) -> Result<String, ParseError> {
+    let ast = parse(input, options.parse_options())?;
+    let term = Term::from_ast(&ast);
+    let (_reduced, reduced) = beta_reduce(&term, options.strategy, options.reduction);
+    Ok(ast_to_string(
+        &reduced.to_ast(&mut Vec::new()),
+        options.print,
+    ))
+}
+
+// Convert a serializable value into a plain JS object.
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    serde_wasm_bindgen::to_value(value).unwrap_or_else(|e| JsValue::from_str(&e.to_string()))
+}
+
+// Read an options object from JS; `undefined` and `null` select the defaults.
+fn from_js<T: DeserializeOwned + Default>(value: JsValue) -> Result<T, JsValue> {
+    if value.is_undefined() || value.is_null() {
+        return Ok(T::default());
+    }
+    serde_wasm_bindgen::from_value(value).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// The outcome of parsing in recovery mode.
+#[derive(Serialize)]
+struct ParseReport {
+    // The partial term, with `?` marking the places that failed to parse.
+    term: String,
+    diagnostics: Vec<ParseError>,
+}
+
+/// The outcome of reducing a term to normal form.
+#[derive(Serialize)]
+struct NormalizeReport {
+    term: String,
+    steps: usize,
+    outcome: normalize::Outcome,
+}
+
+/// One step of a reduction sequence, as shown in a history panel.
+#[derive(Serialize)]
+struct TraceStep {
+    before: String,
+    after: String,
+    path: Vec<strategy::Branch>,
+    kind: strategy::StepKind,
+    variable: String,
+    argument: String,
+    renamed: Vec<Rename>,
+    developed: Vec<Vec<Branch>>,
+}
+
+impl TraceStep {
+    fn new(before: &AST, step: &strategy::Step, options: PrintOptions) -> Self {
+        TraceStep {
+            before: ast_to_string(before, options),
+            after: ast_to_string(&step.term, options),
+            path: step.path.clone(),
+            kind: step.kind,
+            variable: step.variable.clone(),
+            argument: ast_to_string(&step.argument, options),
+            renamed: step.renamed.clone(),
+            developed: step.developed.clone(),
+        }
+    }
+}
+
+/// A whole reduction sequence and why it ended.
+#[derive(Serialize)]
+struct TraceReport {
+    steps: Vec<TraceStep>,
+    outcome: normalize::Outcome,
+}
+
+/// A redex the user can choose to contract.
+#[derive(Serialize)]
+struct RedexReport {
+    path: Vec<Branch>,
+    // Where the redex was written, unless it only appears once a definition
+    // is expanded.
+    span: Option<Span>,
+    kind: strategy::StepKind,
+    variable: String,
+    argument: String,
+}
+
+/// A reduction graph, as sent to JS.
+#[derive(Serialize)]
+struct GraphReport {
+    nodes: Vec<GraphNodeReport>,
+    edges: Vec<GraphEdgeReport>,
+    complete: bool,
+    longest_unbounded: bool,
+}
+
+#[derive(Serialize)]
+struct GraphNodeReport {
+    term: String,
+    depth: usize,
+    normal_form: bool,
+    expanded: bool,
+}
+
+#[derive(Serialize)]
+struct GraphEdgeReport {
+    from: usize,
+    to: usize,
+    path: Vec<Branch>,
+    kind: strategy::StepKind,
+    shortest: bool,
+    longest: bool,
+}
+
+impl GraphReport {
+    fn new(graph: graph::Graph, options: PrintOptions) -> Self {
+        GraphReport {
+            nodes: graph
+                .nodes
+                .iter()
+                .map(|node| GraphNodeReport {
+                    term: ast_to_string(&node.term, options),
+                    depth: node.depth,
+                    normal_form: node.normal_form,
+                    expanded: node.expanded,
+                })
+                .collect(),
+            edges: graph
+                .edges
+                .into_iter()
+                .map(|edge| GraphEdgeReport {
+                    from: edge.from,
+                    to: edge.to,
+                    path: edge.path,
+                    kind: edge.kind,
+                    shortest: edge.shortest,
+                    longest: edge.longest,
+                })
+                .collect(),
+            complete: graph.complete,
+            longest_unbounded: graph.longest_unbounded,
+        }
+    }
+}
+
+/// The result of call-by-need evaluation.
+#[derive(Serialize)]
+struct NeedReport {
+    // Missing unless `outcome` is `NormalForm`.
+    term: Option<String>,
+    steps: usize,
+    outcome: normalize::Outcome,
+    thunks: Vec<ThunkReport>,
+    trace: Vec<NeedStep>,
+}
+
+#[derive(Serialize)]
+struct ThunkReport {
+    id: usize,
+    // Missing unless the evaluation was traced.
+    term: Option<String>,
+    forced: usize,
+    copies: usize,
+}
+
+#[derive(Serialize)]
+struct NeedStep {
+    rule: need::Rule,
+    control: String,
+    stack: Vec<String>,
+    cell: Option<CellReport>,
+}
+
+#[derive(Serialize)]
+struct CellReport {
+    id: usize,
+    contents: String,
+}
+
+/// The result of running an abstract machine.
+#[derive(Serialize)]
+struct MachineReport {
+    // Missing unless `outcome` is `NormalForm`.
+    term: Option<String>,
+    steps: usize,
+    outcome: normalize::Outcome,
+    trace: Vec<MachineStep>,
+}
+
+#[derive(Serialize)]
+struct MachineStep {
+    // Missing for the state before the first transition.
+    rule: Option<machine::Rule>,
+    control: String,
+    returning: bool,
+    environment: Vec<BindingReport>,
+    stack: Vec<FrameReport>,
+}
+
+#[derive(Serialize)]
+struct BindingReport {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct FrameReport {
+    kind: machine::FrameKind,
+    term: String,
+}
+
+impl MachineStep {
+    fn new(rule: Option<machine::Rule>, state: &machine::State, options: PrintOptions) -> Self {
+        let print = |ast: &AST| ast_to_string(ast, options);
+        MachineStep {
+            rule,
+            control: print(&state.control),
+            returning: state.returning,
+            environment: state
+                .environment
+                .iter()
+                .map(|(name, value)| BindingReport {
+                    name: name.clone(),
+                    value: print(value),
+                })
+                .collect(),
+            stack: state
+                .stack
+                .iter()
+                .map(|frame| FrameReport {
+                    kind: frame.kind,
+                    term: print(&frame.term),
+                })
+                .collect(),
         }
     }
 }
 
-fn next_beta_reduction_internal(input: &str) -> Result<String, String> {
-    let tokens = tokenize(input);
-    let ast = parse(tokens)?;
-    let (_reduced, reduced_ast) = beta_reduce(&ast);
-    Ok(ast_to_string(&reduced_ast))
+/// Parse without stopping at the first error, for live feedback while the
+/// user is typing. Returns `{ term, diagnostics }`; `diagnostics` is empty
+/// when the input is well formed. `options` is an `Options` object.
+#[wasm_bindgen]
+pub fn parse_diagnostics(input: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let options: Options = from_js(options)?;
+    let (ast, diagnostics) = parse_recovering(input, options.parse_options());
+    Ok(to_js(&ParseReport {
+        term: ast_to_string(&ast, options.print),
+        diagnostics,
+    }))
+}
+
+/// Parse a program of `NAME = term;` definitions followed by a main term and
+/// return the main term with every definition expanded, without reducing.
+/// Throws a `ParseError` object for undefined or cyclic definitions.
+#[wasm_bindgen]
+pub fn expand_program(input: &str, options: JsValue) -> Result<String, JsValue> {
+    let options: Options = from_js(options)?;
+    let ast = parse(input, options.parse_options()).map_err(|e| to_js(&e))?;
+    Ok(ast_to_string(&ast, options.print))
+}
+
+/// List the definitions of a program as `{ name, span, comments }` objects,
+/// where `comments` are the `--` and `{- -}` comments written above each one,
+/// or after it on the line where it ends.
+#[wasm_bindgen]
+pub fn definition_comments(input: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let options: Options = from_js(options)?;
+    let docs = definition_comments_internal(input, options.parse).map_err(|e| to_js(&e))?;
+    Ok(to_js(&docs))
 }
 
+/// Rewrite a program from one notation to another, e.g. with
+/// `{ dialect: "Haskell", print_dialect: "Lisp" }`. Definitions are kept as
+/// definitions; comments are not preserved.
+#[wasm_bindgen]
+pub fn convert(input: &str, options: JsValue) -> Result<String, JsValue> {
+    convert_internal(input, from_js(options)?).map_err(|e| to_js(&e))
+}
+
+/// Always parses leniently, for compatibility with existing callers.
 #[wasm_bindgen]
 pub fn next_beta_reduction_wasm(input: &str) -> String {
-    next_beta_reduction_internal(input).unwrap_or_else(|e| e)
+    let options = Options {
+        parse: ParseOptions {
+            strict: false,
+            ..ParseOptions::default()
+        },
+        ..Options::default()
+    };
+    next_beta_reduction_internal(input, options).unwrap_or_else(|e| e.to_string())
+}
+
+/// Like `next_beta_reduction_wasm`, but throws a structured `ParseError`
+/// object (`message`, `span`, `found`, `expected`) instead of returning the
+/// message in place of the result. `options` is an `Options` object; parsing
+/// is strict unless `{ strict: false }` is passed, and the step follows
+/// `strategy` (e.g. `{ strategy: "CallByValue" }`, normal order by default).
+/// With `{ reduction: "BetaEta" }` the step may also be an η-reduction.
+#[wasm_bindgen]
+pub fn next_beta_reduction(input: &str, options: JsValue) -> Result<String, JsValue> {
+    next_beta_reduction_internal(input, from_js(options)?).map_err(|e| to_js(&e))
+}
+
+/// Reduce a term until no step of `strategy` applies or `max_steps` steps
+/// have been taken. Returns `{ term, steps, outcome }`, so callers need no
+/// loop of their own. `outcome` is `"NormalForm"`, `"BudgetExhausted"`,
+/// `{ Cycle: { period } }` when the term came back to an α-equivalent one,
+/// or `{ Growing: { period } }` when the budget ran out while the term kept
+/// growing in a repeating pattern. Throws a `ParseError` object if the input does not parse.
+#[wasm_bindgen]
+pub fn normalize(input: &str, max_steps: u32, options: JsValue) -> Result<JsValue, JsValue> {
+    let report =
+        normalize_internal(input, max_steps as usize, from_js(options)?).map_err(|e| to_js(&e))?;
+    Ok(to_js(&report))
+}
+
+/// Reduce like `normalize`, but return every step as
+/// `{ before, after, path, kind, variable, argument, renamed }`, where `path`
+/// leads from the root to the contracted redex (e.g. `["Body", "Function"]`),
+/// `variable` and `argument` are its bound variable and the term substituted
+/// for it, and `renamed` lists the binders renamed to avoid capture as
+/// `{ from, to }`. `developed` holds the paths of every redex contracted by
+/// the step: with `{ strategy: "Parallel" }` each step is a complete
+/// development, contracting all the redexes of the term at once, and
+/// `path`, `variable` and `argument` describe the first of them. Returns
+/// `{ steps, outcome }`.
+#[wasm_bindgen]
+pub fn trace(input: &str, max_steps: u32, options: JsValue) -> Result<JsValue, JsValue> {
+    let report =
+        trace_internal(input, max_steps as usize, from_js(options)?).map_err(|e| to_js(&e))?;
+    Ok(to_js(&report))
+}
+
+/// List every redex of a term as `{ path, span, kind, variable, argument }`,
+/// outermost first, so the user can pick one to contract with `reduce_at`.
+/// `span` is `null` for redexes that are not written out in the input.
+#[wasm_bindgen]
+pub fn redexes(input: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let redexes = redexes_internal(input, from_js(options)?).map_err(|e| to_js(&e))?;
+    Ok(to_js(&redexes))
+}
+
+/// Contract the redex at `path`, as listed by `redexes`, and return the step
+/// in the same form as each step of `trace`. Throws if there is no redex
+/// there.
+#[wasm_bindgen]
+pub fn reduce_at(input: &str, path: JsValue, options: JsValue) -> Result<JsValue, JsValue> {
+    let options: Options = from_js(options)?;
+    let path: Vec<Branch> = from_js(path)?;
+    let ast = parse(input, options.parse_options()).map_err(|e| to_js(&e))?;
+    let contraction = redex::reduce_at(&Term::from_ast(&ast), &path)
+        .ok_or_else(|| JsValue::from_str("No redex at the given path"))?;
+    let step = strategy::Step::new(&ast, &contraction);
+    Ok(to_js(&TraceStep::new(&ast, &step, options.print)))
+}
+
+/// η-expand a term `M` to `λx.M x`, choosing an `x` that is not free in `M`.
+#[wasm_bindgen]
+pub fn eta_expand(input: &str, options: JsValue) -> Result<String, JsValue> {
+    let options: Options = from_js(options)?;
+    let ast = parse(input, options.parse_options()).map_err(|e| to_js(&e))?;
+    Ok(ast_to_string(&strategy::eta_expand(&ast), options.print))
+}
+
+/// Whether two terms are the same up to the names of their bound variables,
+/// so that `λx.x` and `λy.y` compare equal. Definitions are expanded first.
+#[wasm_bindgen]
+pub fn alpha_equivalent(a: &str, b: &str, options: JsValue) -> Result<bool, JsValue> {
+    let options: Options = from_js(options)?;
+    let a = parse(a, options.parse_options()).map_err(|e| to_js(&e))?;
+    let b = parse(b, options.parse_options()).map_err(|e| to_js(&e))?;
+    Ok(alpha::alpha_equivalent(&a, &b))
+}
+
+/// Rename every binder deterministically: `a` for the outermost, `b` for the
+/// next one in, and so on, avoiding the term's free variables. α-equivalent
+/// terms print identically afterwards.
+#[wasm_bindgen]
+pub fn canonical_rename(input: &str, options: JsValue) -> Result<String, JsValue> {
+    let options: Options = from_js(options)?;
+    let ast = parse(input, options.parse_options()).map_err(|e| to_js(&e))?;
+    Ok(ast_to_string(&alpha::canonical(&ast), options.print))
+}
+
+/// Explore the reduction graph of a term: every term reachable by contracting
+/// any one redex, up to α-equivalence, with `max_nodes` nodes and reductions
+/// of `max_depth` steps at most. Returns `{ nodes, edges, complete,
+/// longest_unbounded }`, where node 0 is the input, each node is `{ term,
+/// depth, normal_form, expanded }` and each edge `{ from, to, path, kind,
+/// shortest, longest }`, the last two marking the shortest and longest
+/// reductions found to a normal form.
+#[wasm_bindgen]
+pub fn reduction_graph(
+    input: &str,
+    max_nodes: u32,
+    max_depth: u32,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let options: Options = from_js(options)?;
+    let graph = reduction_graph_internal(input, max_nodes as usize, max_depth as usize, options)
+        .map_err(|e| to_js(&e))?;
+    Ok(to_js(&GraphReport::new(graph, options.print)))
+}
+
+/// `reduction_graph`, written in Graphviz DOT.
+#[wasm_bindgen]
+pub fn reduction_graph_dot(
+    input: &str,
+    max_nodes: u32,
+    max_depth: u32,
+    options: JsValue,
+) -> Result<String, JsValue> {
+    let options: Options = from_js(options)?;
+    let graph = reduction_graph_internal(input, max_nodes as usize, max_depth as usize, options)
+        .map_err(|e| to_js(&e))?;
+    Ok(graph::to_dot(&graph, options.print))
+}
+
+/// Evaluate a term call-by-need, sharing each argument through a thunk that
+/// is updated with its value the first time it is needed, for at most
+/// `max_steps` machine transitions. Returns `{ term, steps, outcome, thunks,
+/// trace }`: `term` is the normal form (or `null` if it was not reached),
+/// each of `thunks` is `{ id, term, forced, copies }`, saying how many times
+/// the thunk was evaluated (at most once) against how many times its value
+/// was used, which is how many copies call-by-name would have evaluated, and
+/// each entry of `trace` is `{ rule, control, stack, cell }`, where `#n`
+/// refers to heap cell `n`, so repeated `#n`s are shared nodes. `cell` is
+/// the `{ id, contents }` of the cell the step allocated or updated.
+///
+/// Every entry of the trace prints the whole term and stack, so the trace,
+/// and the `term` of each thunk, are only filled in when `trace` is true;
+/// otherwise `trace` is empty and each thunk's `term` is `null`.
+#[wasm_bindgen]
+pub fn evaluate_by_need(
+    input: &str,
+    max_steps: u32,
+    trace: bool,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let report = evaluate_by_need_internal(input, max_steps as usize, trace, from_js(options)?)
+        .map_err(|e| to_js(&e))?;
+    Ok(to_js(&report))
+}
+
+/// Run an abstract machine on a term for at most `max_steps` transitions:
+/// `machine` is `"Krivine"`, which evaluates call-by-name to weak head normal
+/// form, or `"Cek"`, which evaluates call-by-value to a value. Returns `{
+/// term, steps, outcome, trace }`: `term` is the term the final state stands
+/// for (or `null` if the machine did not halt), the same as `normalize` with
+/// the `WeakHead` or `CallByValue` strategy gives, and each entry of `trace`
+/// is the state after a transition, `{ rule, control, returning,
+/// environment, stack }`. `control` is the term under evaluation, or the
+/// value being returned if `returning`; `environment` lists the `{ name,
+/// value }` of the variables it refers to, innermost first; `stack` lists
+/// the `{ kind, term }` of each frame, top first, where `kind` is
+/// `"Argument"` or, for the CEK machine, `"Function"`.
+///
+/// Each entry of the trace reads back the whole state, so the trace is only
+/// filled in when `trace` is true; otherwise it is empty.
+#[wasm_bindgen]
+pub fn run_machine(
+    input: &str,
+    machine: JsValue,
+    max_steps: u32,
+    trace: bool,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let report = run_machine_internal(
+        input,
+        from_js(machine)?,
+        max_steps as usize,
+        trace,
+        from_js(options)?,
+    )
+    .map_err(|e| to_js(&e))?;
+    Ok(to_js(&report))
+}
+
+/// An abstract machine held by JS and stepped one transition at a time, so
+/// the user can watch each rule fire. `run_machine` makes the same
+/// transitions in one go.
+#[wasm_bindgen]
+pub struct MachineHandle {
+    machine: machine::Machine,
+    // The rule of the last transition, if there has been one.
+    rule: Option<machine::Rule>,
+    print: PrintOptions,
+}
+
+#[wasm_bindgen]
+impl MachineHandle {
+    /// Load a term into a machine: `machine` is `"Krivine"` or `"Cek"`, as
+    /// for `run_machine`. Throws a `ParseError` object if the input does not
+    /// parse.
+    #[wasm_bindgen(constructor)]
+    pub fn new(input: &str, machine: JsValue, options: JsValue) -> Result<MachineHandle, JsValue> {
+        MachineHandle::load(input, from_js(machine)?, from_js(options)?).map_err(|e| to_js(&e))
+    }
+
+    /// Make one transition and return its rule, or `null` once the machine
+    /// has halted.
+    pub fn step(&mut self) -> JsValue {
+        match self.advance() {
+            Some(rule) => to_js(&rule),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Whether the machine has halted, so that `step` would return `null`.
+    pub fn halted(&self) -> bool {
+        self.machine.halted()
+    }
+
+    /// The current state, in the same form as each entry of the trace of
+    /// `run_machine`: `{ rule, control, returning, environment, stack }`,
+    /// where `rule` is that of the last transition, or `null` before the
+    /// first.
+    pub fn state(&self) -> JsValue {
+        to_js(&self.report())
+    }
+}
+
+impl MachineHandle {
+    fn load(input: &str, kind: machine::Kind, options: Options) -> Result<Self, ParseError> {
+        let ast = parse(input, options.parse_options())?;
+        Ok(MachineHandle {
+            machine: machine::Machine::new(kind, &Term::from_ast(&ast)),
+            rule: None,
+            print: options.print,
+        })
+    }
+
+    fn advance(&mut self) -> Option<machine::Rule> {
+        let rule = self.machine.step()?;
+        self.rule = Some(rule);
+        Some(rule)
+    }
+
+    fn report(&self) -> MachineStep {
+        MachineStep::new(self.rule, &self.machine.state(), self.print)
+    }
+}
+
+#[cfg(test)]
+mod parse_tests {
+    use super::*;
+
+    fn print(ast: &AST) -> String {
+        ast_to_string(ast, PrintOptions::default())
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let table = [
+            (
+                "(a b.c",
+                "Expected ')'",
+                Span::new(4, 5),
+                Some(Token::Dot),
+                &[TokenKind::RParen][..],
+            ),
+            (
+                "λx)",
+                "Expected '.' after lambda parameter",
+                Span::new(2, 3),
+                Some(Token::RParen),
+                &[TokenKind::Dot, TokenKind::Identifier, TokenKind::Comma],
+            ),
+            (
+                "λ.x",
+                "Expected identifier after lambda",
+                Span::new(1, 2),
+                Some(Token::Dot),
+                &[TokenKind::Identifier],
+            ),
+            // At the end of the input the span is empty.
+            (
+                "λx.",
+                "Unexpected end of input",
+                Span::new(3, 3),
+                None,
+                FACTOR_START,
+            ),
+        ];
+        for (input, message, span, found, expected) in table {
+            let error = parse(input, ParseOptions::default()).unwrap_err();
+            assert_eq!(
+                error,
+                ParseError {
+                    message: message.to_string(),
+                    span,
+                    found,
+                    expected: expected.to_vec(),
+                },
+                "{input}"
+            );
+        }
+
+        // `𝑥` is one char, but two UTF-16 code units.
+        let error = parse("(𝑥 y", ParseOptions::default()).unwrap_err();
+        assert_eq!((error.span, error.found), (Span::new(4, 4), None));
+    }
+
+    #[test]
+    fn recovery_reports_every_error() {
+        let (ast, diagnostics) = parse_recovering("(λx. ) (λ.y) )", ParseOptions::default());
+        // Each missing piece is an `AST::Error` node, printed as `?`.
+        assert_eq!(print(&ast), "(λx.?) λ?.y");
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|e| (e.message.as_str(), e.span, e.found.clone()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "Unexpected token: RParen",
+                    Span::new(5, 6),
+                    Some(Token::RParen)
+                ),
+                (
+                    "Expected identifier after lambda",
+                    Span::new(9, 10),
+                    Some(Token::Dot)
+                ),
+                (TRAILING_TOKEN, Span::new(13, 14), Some(Token::RParen)),
+            ]
+        );
+        assert_eq!(diagnostics[1].expected, [TokenKind::Identifier]);
+
+        // Without recovery only the first is reported.
+        let error = parse("(λx. ) (λ.y) )", ParseOptions::default()).unwrap_err();
+        assert_eq!(error, diagnostics[0]);
+
+        let (ast, diagnostics) = parse_recovering("λx.x y", ParseOptions::default());
+        assert_eq!(print(&ast), "λx.x y");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn strict_and_lenient() {
+        let strict = ParseOptions::default();
+        let lenient = ParseOptions {
+            strict: false,
+            ..strict
+        };
+        let error = parse("x # y", strict).unwrap_err();
+        assert_eq!(error.message, "Unexpected character '#'");
+        assert_eq!((error.span, error.found), (Span::new(2, 3), None));
+        assert_eq!(print(&parse("x # y", lenient).unwrap()), "x y");
+
+        for (input, span, kept) in [
+            ("λx.x)", Span::new(4, 5), "λx.x"),
+            ("a ) b", Span::new(2, 3), "a"),
+        ] {
+            let error = parse(input, strict).unwrap_err();
+            assert_eq!(error.message, TRAILING_TOKEN);
+            assert_eq!((error.span, error.found), (span, Some(Token::RParen)));
+            // Leniently, everything from the stray token on is dropped.
+            assert_eq!(print(&parse(input, lenient).unwrap()), kept);
+        }
+
+        // Syntax errors are errors either way.
+        assert!(parse("(a b", lenient).is_err());
+    }
+
+    #[test]
+    fn multiple_binders() {
+        let options = ParseOptions::default();
+        for input in ["λf x.f (f x)", "λf,x.f (f x)", "λf, x.f (f x)"] {
+            let ast = parse(input, options).unwrap();
+            assert_eq!(print(&ast), "λf.λx.f (f x)", "{input}");
+        }
+        let compact = PrintOptions {
+            compact_binders: true,
+            ..PrintOptions::default()
+        };
+        let ast = parse("λf.λx.f (f x)", options).unwrap();
+        assert_eq!(ast_to_string(&ast, compact), "λf x.f (f x)");
+
+        // A ',' must be followed by another binder.
+        let error = parse("λf,.x", options).unwrap_err();
+        assert_eq!(
+            (error.message.as_str(), error.span, error.found),
+            (
+                "Expected identifier after ','",
+                Span::new(3, 4),
+                Some(Token::Dot)
+            )
+        );
+        assert_eq!(error.expected, [TokenKind::Identifier]);
+    }
+
+    #[test]
+    fn let_and_letrec() {
+        let expand = |input: &str, options: ParseOptions| print(&parse(input, options).unwrap());
+        let options = ParseOptions::default();
+        assert_eq!(expand("let x = a in x x", options), "(λx.x x) a");
+        assert_eq!(
+            expand("let f = λx.x in let g = f in g g", options),
+            "(λf.(λg.g g) f) λx.x"
+        );
+        assert_eq!(
+            expand("letrec f = λn.f n in f", options),
+            "(λf.f) ((λf.(λx.f (x x)) λx.f (x x)) λf.λn.f n)"
+        );
+        let z = ParseOptions {
+            fixpoint: Some(Fixpoint::Z),
+            ..options
+        };
+        assert_eq!(
+            expand("letrec f = λn.f n in f", z),
+            "(λf.f) ((λf.(λx.f λv.x x v) λx.f λv.x x v) λf.λn.f n)"
+        );
+
+        let kept = ParseOptions {
+            keep_let: true,
+            ..options
+        };
+        let ast = parse("letrec f = λn.f n in f", kept).unwrap();
+        assert_eq!(print(&ast), "letrec f = λn.f n in f");
+        assert_eq!(
+            print(&expand_lets(&ast)),
+            expand("letrec f = λn.f n in f", options)
+        );
+    }
 }
@@ -0,0 +1,596 @@
+//! Abstract machines: the Krivine machine, which evaluates call-by-name to
+//! weak head normal form, and the CEK machine, which evaluates call-by-value
+//! to a value.
+//!
+//! Both run on the nameless core. The control is a subterm of the input
+//! paired with an environment, a list of closures indexed by de Bruijn
+//! index, and nothing is ever substituted: a variable is looked up when it
+//! is reached. The stack of the Krivine machine holds the arguments waiting
+//! to be applied. The continuation of the CEK machine holds, for each
+//! application under evaluation, either its argument, still to be evaluated,
+//! or the value of its function, waiting for the argument's.
+//!
+//! A machine state stands for a term: its closures with their environments
+//! substituted in, plugged into the stack. It is the term the rewriting
+//! engine would have reached, so a halted Krivine machine gives what
+//! `WeakHead` normalizes to, and a halted CEK machine what `CallByValue`
+//! normalizes to. In the CEK machine a free variable is a value, as it is
+//! for `CallByValue`, but an application that cannot be contracted is not:
+//! it is kept as a stuck term, and evaluation goes on around it.
+//!
+//! A kept `let` is expanded when it is reached, as the rewriting engine does.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::env;
+use super::nameless::{Node, Term};
+use super::normalize::Outcome;
+use super::AST;
+
+/// Which machine to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Kind {
+    /// Call-by-name, to weak head normal form.
+    #[default]
+    Krivine,
+    /// Call-by-value, to a value.
+    Cek,
+}
+
+/// A rule of the machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Rule {
+    /// Evaluate the function of an application, pushing its argument, with
+    /// the environment, on the stack.
+    Push,
+    /// Apply an abstraction to the argument on top of the stack, binding
+    /// its variable to the argument's closure.
+    Beta,
+    /// Replace a variable with its closure from the environment.
+    Lookup,
+    /// Expand a kept `let`.
+    Let,
+    /// (CEK) An abstraction or a free variable is a value: return it.
+    Value,
+    /// (CEK) The function of an application is a value: remember it and
+    /// evaluate the argument.
+    Argument,
+    /// (CEK) An application whose function is not an abstraction, or whose
+    /// argument is not a value, cannot be contracted: return it as it is.
+    Stuck,
+}
+
+/// A machine state, read back into terms.
+pub struct State {
+    /// The term under evaluation, or (in the CEK machine) the value being
+    /// returned, with its variables named after their binders.
+    pub control: AST,
+    /// Whether `control` is a value being returned rather than a term to
+    /// evaluate.
+    pub returning: bool,
+    /// The innermost bindings of the control's environment, as far out as
+    /// the control refers, innermost first: each variable's name with the
+    /// term its closure stands for.
+    pub environment: Vec<(String, AST)>,
+    /// Top of the stack first.
+    pub stack: Vec<Frame>,
+}
+
+/// An entry of the stack, with the term its closure or value stands for.
+pub struct Frame {
+    pub kind: FrameKind,
+    pub term: AST,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FrameKind {
+    /// An argument waiting to be applied, or (in the CEK machine) to be
+    /// evaluated once the function is a value.
+    Argument,
+    /// (CEK) The value of a function, waiting for its argument's.
+    Function,
+}
+
+/// One transition of a machine.
+pub struct TraceEntry {
+    pub rule: Rule,
+    /// The state after the transition.
+    pub state: State,
+}
+
+pub struct Run {
+    /// The term the final state stands for, if the machine halted.
+    pub term: Option<AST>,
+    pub steps: usize,
+    /// `NormalForm` once the machine halts.
+    pub outcome: Outcome,
+    /// Empty unless the run was traced.
+    pub trace: Vec<TraceEntry>,
+}
+
+/// Run `kind` on `ast` for at most `max_steps` transitions. With `trace`,
+/// the state after every transition is recorded; reading one back takes
+/// time in the size of the whole state, so this is left out otherwise.
+pub fn run(ast: &AST, kind: Kind, max_steps: usize, trace: bool) -> Run {
+    let mut machine = Machine::new(kind, &Term::from_ast(ast));
+    let mut steps = 0;
+    let mut entries = Vec::new();
+    let outcome = loop {
+        if machine.halted() {
+            break Outcome::NormalForm;
+        }
+        if steps == max_steps {
+            break Outcome::BudgetExhausted;
+        }
+        let rule = machine.step().expect("the machine has not halted");
+        steps += 1;
+        if trace {
+            entries.push(TraceEntry {
+                rule,
+                state: machine.state(),
+            });
+        }
+    };
+    Run {
+        term: (outcome == Outcome::NormalForm).then(|| machine.term().to_ast(&mut Vec::new())),
+        steps,
+        outcome,
+        trace: entries,
+    }
+}
+
+/// A Krivine or CEK machine, to be run one transition at a time.
+pub struct Machine {
+    kind: Kind,
+    control: Control,
+    stack: Vec<Pending>,
+}
+
+impl Machine {
+    /// A machine about to evaluate `term`, which must have no loose indices.
+    pub fn new(kind: Kind, term: &Term) -> Machine {
+        Machine {
+            kind,
+            control: Control::Eval(Closure {
+                term: term.clone(),
+                env: None,
+            }),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Make one transition, or return `None` if the machine has halted.
+    pub fn step(&mut self) -> Option<Rule> {
+        match self.kind {
+            Kind::Krivine => self.krivine(),
+            Kind::Cek => self.cek(),
+        }
+    }
+
+    /// Whether the machine has halted, so that `step` would return `None`.
+    pub fn halted(&self) -> bool {
+        match &self.control {
+            Control::Eval(closure) => match &*closure.term {
+                Node::Lambda { .. } => self.kind == Kind::Krivine && self.stack.is_empty(),
+                Node::Free(_) | Node::Error => self.kind == Kind::Krivine,
+                Node::App(..) | Node::Bound(_) | Node::Let { .. } => false,
+            },
+            Control::Return(_) => self.stack.is_empty(),
+        }
+    }
+
+    /// The current state, read back.
+    pub fn state(&self) -> State {
+        let mut memo = Memo::new();
+        let (returning, (control, environment)) = match &self.control {
+            Control::Eval(closure) | Control::Return(Value::Closure(closure)) => (
+                matches!(self.control, Control::Return(_)),
+                show(closure, &mut memo),
+            ),
+            Control::Return(Value::Stuck(term)) => {
+                (true, (term.to_ast(&mut Vec::new()), Vec::new()))
+            }
+        };
+        let stack = self
+            .stack
+            .iter()
+            .rev()
+            .map(|pending| {
+                let (kind, term) = match pending {
+                    Pending::Argument(closure) => (FrameKind::Argument, unload(closure, &mut memo)),
+                    Pending::Function(value) => (FrameKind::Function, value.unload(&mut memo)),
+                };
+                Frame {
+                    kind,
+                    term: term.to_ast(&mut Vec::new()),
+                }
+            })
+            .collect();
+        State {
+            control,
+            returning,
+            environment,
+            stack,
+        }
+    }
+
+    /// The term the state stands for: the control, with its environment
+    /// substituted in, plugged into the stack.
+    pub fn term(&self) -> Term {
+        let mut memo = Memo::new();
+        let mut term = match &self.control {
+            Control::Eval(closure) => unload(closure, &mut memo),
+            Control::Return(value) => value.unload(&mut memo),
+        };
+        for pending in self.stack.iter().rev() {
+            term = Term::new(match pending {
+                Pending::Argument(closure) => Node::App(term, unload(closure, &mut memo)),
+                Pending::Function(value) => Node::App(value.unload(&mut memo), term),
+            });
+        }
+        term
+    }
+
+    fn krivine(&mut self) -> Option<Rule> {
+        let Control::Eval(closure) = &self.control else {
+            unreachable!("the Krivine machine never returns a value");
+        };
+        let closure = closure.clone();
+        let (rule, next) = match &*closure.term {
+            Node::App(function, argument) => {
+                self.stack.push(Pending::Argument(closure.with(argument)));
+                (Rule::Push, closure.with(function))
+            }
+            Node::Lambda { name, body } => {
+                let Some(Pending::Argument(argument)) = self.stack.pop() else {
+                    return None;
+                };
+                (Rule::Beta, closure.bind(name, body, argument))
+            }
+            Node::Bound(index) => (Rule::Lookup, lookup(&closure.env, *index).clone()),
+            Node::Let { .. } => (Rule::Let, closure.with(&closure.term.expand_let())),
+            Node::Free(_) | Node::Error => return None,
+        };
+        self.control = Control::Eval(next);
+        Some(rule)
+    }
+
+    fn cek(&mut self) -> Option<Rule> {
+        let (rule, next) = match &self.control {
+            Control::Eval(closure) => match &*closure.term {
+                Node::App(function, argument) => {
+                    self.stack.push(Pending::Argument(closure.with(argument)));
+                    (Rule::Push, Control::Eval(closure.with(function)))
+                }
+                Node::Lambda { .. } | Node::Free(_) => (
+                    Rule::Value,
+                    Control::Return(Value::Closure(closure.clone())),
+                ),
+                Node::Bound(index) => (
+                    Rule::Lookup,
+                    Control::Return(Value::Closure(lookup(&closure.env, *index).clone())),
+                ),
+                Node::Let { .. } => (
+                    Rule::Let,
+                    Control::Eval(closure.with(&closure.term.expand_let())),
+                ),
+                Node::Error => (
+                    Rule::Stuck,
+                    Control::Return(Value::Stuck(closure.term.clone())),
+                ),
+            },
+            Control::Return(value) => match self.stack.pop()? {
+                Pending::Argument(argument) => {
+                    self.stack.push(Pending::Function(value.clone()));
+                    (Rule::Argument, Control::Eval(argument))
+                }
+                Pending::Function(Value::Closure(function)) => match (&*function.term, value) {
+                    (Node::Lambda { name, body }, Value::Closure(argument)) => (
+                        Rule::Beta,
+                        Control::Eval(function.bind(name, body, argument.clone())),
+                    ),
+                    _ => (Rule::Stuck, stuck(&Value::Closure(function), value)),
+                },
+                Pending::Function(function) => (Rule::Stuck, stuck(&function, value)),
+            },
+        };
+        self.control = next;
+        Some(rule)
+    }
+}
+
+// The value of an application that cannot be contracted.
+fn stuck(function: &Value, argument: &Value) -> Control {
+    let mut memo = Memo::new();
+    Control::Return(Value::Stuck(Term::new(Node::App(
+        function.unload(&mut memo),
+        argument.unload(&mut memo),
+    ))))
+}
+
+enum Control {
+    Eval(Closure),
+    // The CEK machine only.
+    Return(Value),
+}
+
+enum Pending {
+    Argument(Closure),
+    // The CEK machine only.
+    Function(Value),
+}
+
+#[derive(Clone)]
+enum Value {
+    // An abstraction or a free variable.
+    Closure(Closure),
+    // A term in weak normal form that is not a value, with no loose
+    // indices.
+    Stuck(Term),
+}
+
+impl Value {
+    fn unload(&self, memo: &mut Memo) -> Term {
+        match self {
+            Value::Closure(closure) => unload(closure, memo),
+            Value::Stuck(term) => term.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Closure {
+    term: Term,
+    env: Env,
+}
+
+impl Closure {
+    // A subterm of the closure's term, in the same environment.
+    fn with(&self, term: &Term) -> Closure {
+        Closure {
+            term: term.clone(),
+            env: self.env.clone(),
+        }
+    }
+
+    // The body of an abstraction in the closure, with its variable bound to
+    // `argument`.
+    fn bind(&self, name: &str, body: &Term, argument: Closure) -> Closure {
+        Closure {
+            term: body.clone(),
+            env: env::bind(&self.env, name.to_string(), argument),
+        }
+    }
+}
+
+// Each variable is bound to the closure of its argument.
+type Env = env::Env<String, Closure>;
+type Binding = env::Binding<String, Closure>;
+
+impl env::Value<String> for Closure {
+    fn take_env(&mut self) -> Env {
+        self.env.take()
+    }
+}
+
+fn lookup(env: &Env, index: usize) -> &Closure {
+    &env::bindings(env)
+        .nth(index)
+        .expect("bound in the environment")
+        .value
+}
+
+// The terms of the bindings unloaded so far, by address.
+type Memo = HashMap<*const Binding, Term>;
+
+// The term a closure stands for: its term with the terms of the closures
+// its variables are bound to substituted in. Closures nest as deeply as the
+// evaluation went, so the bindings a closure needs are unloaded first from
+// an explicit stack.
+fn unload(closure: &Closure, memo: &mut Memo) -> Term {
+    // Each binding is pushed once to unload those it needs, then again to
+    // be unloaded itself.
+    let mut tasks: Vec<(&Binding, bool)> =
+        needed(closure).map(|binding| (binding, false)).collect();
+    while let Some((binding, ready)) = tasks.pop() {
+        let key: *const Binding = binding;
+        if memo.contains_key(&key) {
+            continue;
+        }
+        if ready {
+            let term = substitute(&binding.value, memo);
+            memo.insert(key, term);
+        } else {
+            tasks.push((binding, true));
+            tasks.extend(needed(&binding.value).map(|binding| (binding, false)));
+        }
+    }
+    substitute(closure, memo)
+}
+
+// The bindings a closure's term refers to, and perhaps some others,
+// innermost first.
+fn needed(closure: &Closure) -> impl Iterator<Item = &Binding> {
+    env::bindings(&closure.env).take(closure.term.loose())
+}
+
+// A closure's term with the terms of the bindings it refers to, all already
+// unloaded, substituted in.
+fn substitute(closure: &Closure, memo: &Memo) -> Term {
+    let values: Vec<&Term> = needed(closure)
+        .map(|binding| &memo[&(binding as *const Binding)])
+        .collect();
+    closure.term.close(|index| values[index].clone())
+}
+
+// The closure's term, with its variables named after their binders, and
+// the bindings it refers to.
+fn show(closure: &Closure, memo: &mut Memo) -> (AST, Vec<(String, AST)>) {
+    let bindings: Vec<&Binding> = needed(closure).collect();
+    // Read the term back under its binders, so that their names are kept
+    // unless they would capture something, then take the binders off.
+    let mut term = closure.term.clone();
+    for binding in &bindings {
+        term = Term::new(Node::Lambda {
+            name: binding.name.clone(),
+            body: term,
+        });
+    }
+    let mut ast = term.to_ast(&mut Vec::new());
+    let mut names = Vec::new();
+    for _ in &bindings {
+        let AST::Lambda { param, body } = &mut ast else {
+            unreachable!("read back as it was built");
+        };
+        names.push(std::mem::take(param));
+        let body = std::mem::replace(&mut **body, AST::Error);
+        ast = body;
+    }
+    let environment = names
+        .into_iter()
+        .rev()
+        .zip(bindings)
+        .map(|(name, binding)| (name, unload(&binding.value, memo).to_ast(&mut Vec::new())))
+        .collect();
+    (ast, environment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::{Reduction, Strategy};
+    use crate::{alpha, ast_to_string, normalize, parse, ParseOptions, PrintOptions};
+    use crate::{run_machine_internal, MachineHandle, Options};
+
+    fn parse_default(input: &str) -> AST {
+        parse(input, ParseOptions::default()).expect("the term parses")
+    }
+
+    #[test]
+    fn machines_agree_with_rewriting() {
+        let inputs = [
+            "(λn f x.f (n f x)) (λf x.f (f x))",
+            "(λx y.y x) ((λz.z) a) ((λz.z) b)",
+            "(λf.f ((λx.x) y)) (λz.z z)",
+            "((λx.x) (a b)) ((λx.x) c)",
+            "let twice = λf x.f (f x) in twice twice (λz.z)",
+            "(λx.λy.x) y",
+        ];
+        for input in inputs {
+            for keep_let in [false, true] {
+                for (kind, strategy) in [
+                    (Kind::Krivine, Strategy::WeakHead),
+                    (Kind::Cek, Strategy::CallByValue),
+                ] {
+                    let options = ParseOptions {
+                        keep_let,
+                        ..ParseOptions::default()
+                    };
+                    let ast = parse(input, options).expect("the term parses");
+                    let run = super::run(&ast, kind, 1000, false);
+                    assert_eq!(run.outcome, Outcome::NormalForm);
+                    let rewritten = normalize::normalize(ast, strategy, Reduction::Beta, 1000);
+                    assert_eq!(rewritten.outcome, Outcome::NormalForm);
+                    let term = run.term.expect("the machine halted");
+                    assert!(
+                        alpha::alpha_equivalent(&term, &rewritten.term),
+                        "{input} on {kind:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn machine_states() {
+        let print = |ast: &AST| ast_to_string(ast, PrintOptions::default());
+        let ast = parse_default("(λx y.x) (λz.z) w");
+        let run = super::run(&ast, Kind::Krivine, 10, true);
+        let rules: Vec<_> = run.trace.iter().map(|entry| entry.rule).collect();
+        use Rule::*;
+        assert_eq!(rules, [Push, Push, Beta, Beta, Lookup]);
+        let state = &run.trace[3].state;
+        assert_eq!(print(&state.control), "x");
+        let environment: Vec<_> = state
+            .environment
+            .iter()
+            .map(|(name, value)| (name.as_str(), print(value)))
+            .collect();
+        assert_eq!(
+            environment,
+            [("y", "w".to_string()), ("x", "λz.z".to_string())]
+        );
+        assert!(state.stack.is_empty());
+
+        let run = super::run(&ast, Kind::Cek, 20, true);
+        let state = &run.trace[2].state;
+        assert_eq!(print(&state.control), "λx.λy.x");
+        assert!(state.returning);
+        let stack: Vec<_> = state
+            .stack
+            .iter()
+            .map(|frame| (frame.kind, print(&frame.term)))
+            .collect();
+        assert_eq!(
+            stack,
+            [
+                (FrameKind::Argument, "λz.z".to_string()),
+                (FrameKind::Argument, "w".to_string()),
+            ]
+        );
+        assert_eq!(run.outcome, Outcome::NormalForm);
+        // Untraced, the same transitions are counted but no states are kept.
+        let untraced = super::run(&ast, Kind::Cek, 20, false);
+        assert_eq!(untraced.steps, run.steps);
+        assert!(untraced.trace.is_empty());
+        assert_eq!(print(&run.term.expect("halted")), "λz.z");
+    }
+
+    #[test]
+    fn step_budget() {
+        let ast = parse_default("(λx y.x) (λz.z) w");
+        let run = super::run(&ast, Kind::Krivine, 5, false);
+        assert_eq!((run.outcome, run.steps), (Outcome::NormalForm, 5));
+        let run = super::run(&ast, Kind::Krivine, 4, false);
+        assert_eq!((run.outcome, run.steps), (Outcome::BudgetExhausted, 4));
+        assert!(run.term.is_none());
+
+        let mut machine = Machine::new(Kind::Cek, &Term::from_ast(&ast));
+        while !machine.halted() {
+            machine.step().expect("a machine that has not halted steps");
+        }
+        assert!(machine.step().is_none());
+    }
+
+    #[test]
+    fn handle_steps_through_the_trace() {
+        // Stepping a handle by hand passes through the states `run_machine`
+        // traces, starting from the state before any rule.
+        let input = "(λx y.x) ((λz.z) v) w";
+        for kind in [Kind::Krivine, Kind::Cek] {
+            let run = run_machine_internal(input, kind, 100, true, Options::default()).unwrap();
+            let mut handle = MachineHandle::load(input, kind, Options::default()).unwrap();
+            let state = handle.report();
+            assert_eq!(
+                (state.rule, state.control.as_str()),
+                (None, "(λx.λy.x) ((λz.z) v) w")
+            );
+            for entry in &run.trace {
+                assert_eq!(handle.advance(), entry.rule);
+                let state = handle.report();
+                assert_eq!(
+                    (state.control, state.returning, state.stack.len()),
+                    (entry.control.clone(), entry.returning, entry.stack.len())
+                );
+            }
+            assert!(handle.machine.halted());
+            assert_eq!(handle.advance(), None);
+            assert_eq!(
+                handle.report().rule,
+                run.trace.last().and_then(|entry| entry.rule)
+            );
+        }
+    }
+}
@@ -0,0 +1,1020 @@
+//! The nameless core the reducers work on.
+//!
+//! Bound variables are De Bruijn indices: `Bound(0)` refers to the nearest
+//! enclosing binder, `Bound(1)` to the one around it, and so on, while free
+//! variables keep their names. Substitution then never captures anything,
+//! so it needs no free-variable sets and no renaming.
+//!
+//! Every binder still carries the name the user gave it. Only when a
+//! substitution puts a variable under a binder of the same name does that
+//! binder get a fresh one, and the renaming is recorded. This is done to
+//! each reduct as it is made, so reading a term back finds nothing more to
+//! rename.
+//!
+//! A `let` is kept as a node of its own, binding its body (and, for a
+//! `letrec`, its value), so that paths into a `Term` are the same as paths
+//! into the `AST` it came from.
+//!
+//! Terms are shared rather than copied. Each node is reference counted, so
+//! a step allocates only the nodes it changes: those on the path to the
+//! redex and those the substitution reaches, with every copy of the
+//! argument at one depth being the same node. Each node also records its
+//! size, a hash that ignores binder names, and how far out its variables
+//! point, which lets substitution leave alone the subterms it cannot change
+//! and lets α-equivalent terms be told apart quickly.
+//!
+//! Nodes are not hash-consed: looking every new node up in a table of those
+//! alive cost more than the sharing it found beyond the above.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use super::strategy::{Branch, StepKind};
+use super::traverse::{self, fold, Children, Fold, NameHasher, Scope, Tree};
+use super::{fresh_var, Fixpoint, Rename, AST};
+
+/// A node of the nameless core, shared by every term it is part of, so
+/// cloning it is cheap.
+#[derive(Clone)]
+pub struct Term(Rc<Shared>);
+
+#[derive(Debug)]
+pub enum Node {
+    Bound(usize),
+    Free(String),
+    Error,
+    Lambda {
+        name: String,
+        body: Term,
+    },
+    App(Term, Term),
+    Let {
+        name: String,
+        value: Term,
+        body: Term,
+        fixpoint: Option<Fixpoint>,
+    },
+}
+
+struct Shared {
+    node: Node,
+    // The number of nodes in the term, counting a shared one every time it
+    // occurs.
+    size: usize,
+    // One more than the highest index pointing outside the term, or 0 if
+    // none does.
+    loose: usize,
+    // Whether a variable of the term is free.
+    free: bool,
+    // A hash of the term that ignores the names of its binders.
+    alpha: u64,
+}
+
+thread_local! {
+    // How many nodes are being dropped, each inside the one above it.
+    static DROPPING: Cell<usize> = const { Cell::new(0) };
+    // Each fixed-point combinator, built the first time a `letrec` needs it.
+    static FIXPOINTS: RefCell<HashMap<Fixpoint, Term>> = RefCell::default();
+}
+
+impl Term {
+    /// The term with the given root.
+    pub fn new(node: Node) -> Term {
+        let (size, loose, free, alpha) = match &node {
+            Node::Bound(index) => (1, index + 1, false, hash((0, index))),
+            Node::Free(name) => (1, 0, true, hash((1, name))),
+            Node::Error => (1, 0, false, hash(2)),
+            Node::Lambda { body, .. } => (
+                body.size().saturating_add(1),
+                body.0.loose.saturating_sub(1),
+                body.0.free,
+                hash((3, body.0.alpha)),
+            ),
+            Node::App(left, right) => (
+                left.size().saturating_add(right.size()).saturating_add(1),
+                left.0.loose.max(right.0.loose),
+                left.0.free || right.0.free,
+                hash((4, left.0.alpha, right.0.alpha)),
+            ),
+            Node::Let {
+                value,
+                body,
+                fixpoint,
+                ..
+            } => (
+                value.size().saturating_add(body.size()).saturating_add(1),
+                match fixpoint {
+                    Some(_) => value.0.loose.saturating_sub(1),
+                    None => value.0.loose,
+                }
+                .max(body.0.loose.saturating_sub(1)),
+                value.0.free || body.0.free,
+                hash((5, fixpoint, value.0.alpha, body.0.alpha)),
+            ),
+        };
+        Term(Rc::new(Shared {
+            node,
+            size,
+            loose,
+            free,
+            alpha,
+        }))
+    }
+
+    pub fn from_ast(ast: &AST) -> Term {
+        fold(
+            ast,
+            &mut Indexer {
+                scope: Scope::new(),
+                depth: 0,
+            },
+        )
+    }
+
+    /// Read the term back with named variables, renaming a binder only where
+    /// its own name would capture a variable, and recording each renaming.
+    pub fn to_ast(&self, renamed: &mut Vec<Rename>) -> AST {
+        let mut namer = Namer { names: Vec::new() };
+        match self.respelled(&[], renamed) {
+            Some(term) => fold(&term, &mut namer),
+            None => fold(self, &mut namer),
+        }
+    }
+
+    /// The term with every binder that would capture a variable referenced
+    /// in its scope given a fresh name, recording each renaming, or `None`
+    /// if no binder does. `outer` names the binders around the term,
+    /// outermost first. Both passes over the term are linear in its size.
+    pub fn respelled(&self, outer: &[String], renamed: &mut Vec<Rename>) -> Option<Term> {
+        let mut captures = Captures {
+            outer,
+            scope: Vec::new(),
+            visible: HashMap::default(),
+            captured: Vec::new(),
+        };
+        fold(self, &mut captures);
+        if !captures.captured.contains(&true) {
+            return None;
+        }
+        let mut taken = self.names();
+        taken.extend(outer.iter().map(String::as_str));
+        let mut respeller = Respeller {
+            captured: captures.captured,
+            taken: taken.into_iter().map(str::to_string).collect(),
+            next: 0,
+            chosen: Vec::new(),
+            renamed,
+        };
+        Some(fold(self, &mut respeller))
+    }
+
+    // Every name in the term, bound or free.
+    fn names(&self) -> HashSet<&str> {
+        let mut names = HashSet::new();
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            match &**node {
+                Node::Free(name) | Node::Lambda { name, .. } | Node::Let { name, .. } => {
+                    names.insert(name.as_str());
+                }
+                Node::Bound(_) | Node::App(..) | Node::Error => {}
+            }
+            match node.children() {
+                Children::Zero => {}
+                Children::One((_, child)) => nodes.push(child),
+                Children::Two((_, first), (_, second)) => {
+                    nodes.push(first);
+                    nodes.push(second);
+                }
+            }
+        }
+        names
+    }
+
+    /// The names of the binders whose scope the end of `path` lies in,
+    /// outermost first.
+    pub fn binders_along(&self, path: &[Branch]) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut node = self;
+        for &branch in path {
+            match (&**node, branch) {
+                (Node::Lambda { name, .. } | Node::Let { name, .. }, Branch::Body)
+                | (
+                    Node::Let {
+                        name,
+                        fixpoint: Some(_),
+                        ..
+                    },
+                    Branch::Value,
+                ) => names.push(name.clone()),
+                _ => {}
+            }
+            node = node.child(branch).expect("the path leads to a node");
+        }
+        names
+    }
+
+    /// How many nodes the term has, as a tree.
+    pub fn size(&self) -> usize {
+        self.0.size
+    }
+
+    /// One more than the highest index pointing outside the term, or 0 if
+    /// none does.
+    pub fn loose(&self) -> usize {
+        self.0.loose
+    }
+
+    /// A hash of the term that ignores the names of its binders, so that
+    /// α-equivalent terms hash alike.
+    pub fn alpha_hash(&self) -> u64 {
+        self.0.alpha
+    }
+
+    /// Whether the terms differ only in the names of their binders.
+    pub fn alpha_eq(&self, other: &Term) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some((a, b)) = pairs.pop() {
+            if Rc::ptr_eq(&a.0, &b.0) {
+                continue;
+            }
+            if a.0.alpha != b.0.alpha || a.size() != b.size() {
+                return false;
+            }
+            match (&**a, &**b) {
+                (Node::Bound(i), Node::Bound(j)) if i == j => {}
+                (Node::Free(x), Node::Free(y)) if x == y => {}
+                (Node::Error, Node::Error) => {}
+                (Node::Lambda { body: x, .. }, Node::Lambda { body: y, .. }) => {
+                    pairs.push((x, y));
+                }
+                (Node::App(f, x), Node::App(g, y)) => {
+                    pairs.push((f, g));
+                    pairs.push((x, y));
+                }
+                (
+                    Node::Let {
+                        value: v,
+                        body: x,
+                        fixpoint: p,
+                        ..
+                    },
+                    Node::Let {
+                        value: w,
+                        body: y,
+                        fixpoint: q,
+                        ..
+                    },
+                ) if p == q => {
+                    pairs.push((v, w));
+                    pairs.push((x, y));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Whether going down `branch` of the term passes a binder.
+    pub fn binds(&self, branch: Branch) -> bool {
+        matches!(
+            (&**self, branch),
+            (Node::Lambda { .. } | Node::Let { .. }, Branch::Body)
+                | (
+                    Node::Let {
+                        fixpoint: Some(_),
+                        ..
+                    },
+                    Branch::Value
+                )
+        )
+    }
+
+    /// The node with the given children in place of its own.
+    pub fn rebuild(&self, children: Children<Term>) -> Term {
+        let node = match (&**self, children) {
+            (Node::Lambda { name, .. }, Children::One(body)) => Node::Lambda {
+                name: name.clone(),
+                body,
+            },
+            (Node::App(..), Children::Two(left, right)) => Node::App(left, right),
+            (Node::Let { name, fixpoint, .. }, Children::Two(value, body)) => Node::Let {
+                name: name.clone(),
+                value,
+                body,
+                fixpoint: *fixpoint,
+            },
+            _ => return self.clone(),
+        };
+        Term::new(node)
+    }
+
+    // The term with each bound variable that points past `from` binders
+    // outside it replaced by what `replace` makes of its index and the
+    // number of binders around it within the term. Subterms with no such
+    // variable are kept as they are.
+    fn map_bound(&self, from: usize, replace: impl FnMut(usize, usize) -> Term) -> Term {
+        fold(
+            self,
+            &mut MapBound {
+                from,
+                replace,
+                depth: 0,
+            },
+        )
+    }
+
+    // The variables of the term that are free or point past `from` binders
+    // outside it, and perhaps some others, with the number of binders
+    // around each within the term, in no particular order.
+    fn variables(&self, from: usize) -> impl Iterator<Item = (&Term, usize)> {
+        let mut stack = vec![(self, 0)];
+        std::iter::from_fn(move || loop {
+            let (term, depth) = stack.pop()?;
+            if !term.0.free && term.0.loose <= from + depth {
+                continue;
+            }
+            let mut push = |(branch, child)| {
+                stack.push((child, depth + usize::from(term.binds(branch))));
+            };
+            match term.children() {
+                Children::Zero => return Some((term, depth)),
+                Children::One(child) => push(child),
+                Children::Two(first, second) => {
+                    push(first);
+                    push(second);
+                }
+            }
+        })
+    }
+
+    /// Add `by` to every index that points outside the innermost `cutoff`
+    /// binders.
+    pub fn shifted(&self, by: isize, cutoff: usize) -> Term {
+        self.map_bound(cutoff, |index, _| {
+            Term::new(Node::Bound(
+                index
+                    .checked_add_signed(by)
+                    .expect("shifted index in range"),
+            ))
+        })
+    }
+
+    /// The term with every index pointing outside it replaced by the closed
+    /// term `outside` gives for the number of binders past the term it
+    /// points.
+    pub fn close(&self, mut outside: impl FnMut(usize) -> Term) -> Term {
+        self.map_bound(0, |index, depth| outside(index - depth))
+    }
+
+    /// The body of a binder with its variable replaced by `argument`, which
+    /// lives outside the binder.
+    pub fn instantiate(&self, argument: &Term) -> Term {
+        // The argument shifted to each depth it is needed at.
+        let mut shifted = HashMap::<usize, Term, BuildHasherDefault<NameHasher>>::default();
+        self.map_bound(0, |index, depth| {
+            if index == depth {
+                let argument = shifted
+                    .entry(depth)
+                    .or_insert_with(|| argument.shifted(depth as isize, 0));
+                argument.clone()
+            } else {
+                // One binder fewer lies between this variable and its own.
+                Term::new(Node::Bound(index - 1))
+            }
+        })
+    }
+
+    // Whether the variable `index` binders out is referenced.
+    fn references(&self, index: usize) -> bool {
+        self.variables(index)
+            .any(|(term, depth)| matches!(**term, Node::Bound(bound) if bound == index + depth))
+    }
+
+    /// Contract the term itself if it is a β-redex or a kept `let`.
+    pub fn contract(&self) -> Option<(StepKind, Term)> {
+        match &**self {
+            Node::App(left, right) => match &**left {
+                Node::Lambda { body, .. } => Some((StepKind::Beta, body.instantiate(right))),
+                _ => None,
+            },
+            Node::Let { .. } => Some((StepKind::Let, self.expand_let())),
+            _ => None,
+        }
+    }
+
+    /// Whether the term is an η-redex `λx.M x`, with `x` not free in `M`.
+    pub fn is_eta_redex(&self) -> bool {
+        match &**self {
+            Node::Lambda { body, .. } => matches!(
+                &**body,
+                Node::App(function, argument)
+                    if matches!(**argument, Node::Bound(0)) && !function.references(0)
+            ),
+            _ => false,
+        }
+    }
+
+    /// Contract the term itself if it is an η-redex.
+    pub fn eta_contract(&self) -> Option<Term> {
+        match &**self {
+            Node::Lambda { body, .. } if self.is_eta_redex() => match &**body {
+                Node::App(function, _) => Some(function.shifted(-1, 0)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Desugar a `let` node the way `expand_let` does for an `AST`.
+    pub fn expand_let(&self) -> Term {
+        match &**self {
+            Node::Let {
+                name,
+                value,
+                body,
+                fixpoint,
+            } => {
+                let value = match fixpoint {
+                    Some(fixpoint) => Term::new(Node::App(
+                        combinator(*fixpoint),
+                        Term::new(Node::Lambda {
+                            name: name.clone(),
+                            body: value.clone(),
+                        }),
+                    )),
+                    None => value.clone(),
+                };
+                Term::new(Node::App(
+                    Term::new(Node::Lambda {
+                        name: name.clone(),
+                        body: body.clone(),
+                    }),
+                    value,
+                ))
+            }
+            _ => self.clone(),
+        }
+    }
+
+    pub fn child(&self, branch: Branch) -> Option<&Term> {
+        match (&**self, branch) {
+            (Node::App(left, _), Branch::Function) => Some(left),
+            (Node::App(_, right), Branch::Argument) => Some(right),
+            (Node::Lambda { body, .. } | Node::Let { body, .. }, Branch::Body) => Some(body),
+            (Node::Let { value, .. }, Branch::Value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The node at the end of `path`, if there is one.
+    pub fn subterm(&self, path: &[Branch]) -> Option<&Term> {
+        let mut node = self;
+        for &branch in path {
+            node = node.child(branch)?;
+        }
+        Some(node)
+    }
+
+    /// The term with the node at the end of `path`, which must exist,
+    /// replaced by `new`. Everything off the path is shared with the term.
+    pub fn replace(&self, path: &[Branch], new: Term) -> Term {
+        let mut above = Vec::with_capacity(path.len());
+        let mut node = self;
+        for &branch in path {
+            above.push(node);
+            node = node.child(branch).expect("no node at the end of the path");
+        }
+        above
+            .into_iter()
+            .zip(path)
+            .rev()
+            .fold(new, |child, (node, &branch)| node.with_child(branch, child))
+    }
+
+    // The node with `child` down `branch` instead of its own.
+    fn with_child(&self, branch: Branch, child: Term) -> Term {
+        let node = match (&**self, branch) {
+            (Node::App(_, right), Branch::Function) => Node::App(child, right.clone()),
+            (Node::App(left, _), Branch::Argument) => Node::App(left.clone(), child),
+            (Node::Lambda { name, .. }, Branch::Body) => Node::Lambda {
+                name: name.clone(),
+                body: child,
+            },
+            (
+                Node::Let {
+                    name,
+                    value,
+                    fixpoint,
+                    ..
+                },
+                Branch::Body,
+            ) => Node::Let {
+                name: name.clone(),
+                value: value.clone(),
+                body: child,
+                fixpoint: *fixpoint,
+            },
+            (
+                Node::Let {
+                    name,
+                    body,
+                    fixpoint,
+                    ..
+                },
+                Branch::Value,
+            ) => Node::Let {
+                name: name.clone(),
+                value: child,
+                body: body.clone(),
+                fixpoint: *fixpoint,
+            },
+            _ => panic!("no node at the end of the path"),
+        };
+        Term::new(node)
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = NameHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Deref for Term {
+    type Target = Node;
+
+    fn deref(&self) -> &Node {
+        &self.0.node
+    }
+}
+
+impl fmt::Debug for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.node.fmt(f)
+    }
+}
+
+impl Tree for Term {
+    fn children(&self) -> Children<(Branch, &Term)> {
+        match &**self {
+            Node::Bound(_) | Node::Free(_) | Node::Error => Children::Zero,
+            Node::Lambda { body, .. } => Children::One((Branch::Body, body)),
+            Node::App(left, right) => {
+                Children::Two((Branch::Function, left), (Branch::Argument, right))
+            }
+            Node::Let { value, body, .. } => {
+                Children::Two((Branch::Value, value), (Branch::Body, body))
+            }
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Near the top of a term, drop the children the usual way, which
+        // recurses.
+        let depth = DROPPING.get();
+        if depth < traverse::NATIVE_DEPTH {
+            DROPPING.set(depth + 1);
+            drop(std::mem::replace(&mut self.node, Node::Error));
+            DROPPING.set(depth);
+            return;
+        }
+        // Further down, as for `AST`: take apart the children only this node
+        // kept alive before they are dropped, so that none is dropped with
+        // any below it.
+        let mut orphans = Vec::new();
+        take_children(&mut self.node, &mut orphans);
+        while let Some(Term(orphan)) = orphans.pop() {
+            if let Ok(mut shared) = Rc::try_unwrap(orphan) {
+                take_children(&mut shared.node, &mut orphans);
+            }
+        }
+    }
+}
+
+// The fixed-point combinator as a term.
+fn combinator(fixpoint: Fixpoint) -> Term {
+    FIXPOINTS.with(|fixpoints| {
+        fixpoints
+            .borrow_mut()
+            .entry(fixpoint)
+            .or_insert_with(|| Term::from_ast(&fixpoint.term()))
+            .clone()
+    })
+}
+
+fn take_children(node: &mut Node, orphans: &mut Vec<Term>) {
+    match std::mem::replace(node, Node::Error) {
+        Node::Bound(_) | Node::Free(_) | Node::Error => {}
+        Node::Lambda { body, .. } => orphans.push(body),
+        Node::App(left, right) => orphans.extend([left, right]),
+        Node::Let { value, body, .. } => orphans.extend([value, body]),
+    }
+}
+
+// Turns named variables into indices.
+struct Indexer<'a> {
+    // The level of each enclosing binder: how many binders enclose it.
+    scope: Scope<'a, usize>,
+    depth: usize,
+}
+
+impl<'a> Fold<'a, AST> for Indexer<'a> {
+    type Output = Term;
+
+    fn visit(&mut self, ast: &'a AST) -> Option<Term> {
+        match ast {
+            AST::Var(name) => Some(Term::new(match self.scope.get(name) {
+                Some(level) => Node::Bound(self.depth - 1 - level),
+                None => Node::Free(name.clone()),
+            })),
+            _ => None,
+        }
+    }
+
+    fn descend(&mut self, ast: &'a AST, branch: Branch) {
+        if let Some(name) = traverse::binder(ast, branch) {
+            self.scope.bind(name, self.depth);
+            self.depth += 1;
+        }
+    }
+
+    fn ascend(&mut self, ast: &'a AST, branch: Branch) {
+        if let Some(name) = traverse::binder(ast, branch) {
+            self.scope.unbind(name);
+            self.depth -= 1;
+        }
+    }
+
+    fn build(&mut self, ast: &'a AST, children: Children<Term>) -> Term {
+        Term::new(match (ast, children) {
+            (AST::Lambda { param, .. }, Children::One(body)) => Node::Lambda {
+                name: param.clone(),
+                body,
+            },
+            (AST::App(..), Children::Two(left, right)) => Node::App(left, right),
+            (AST::Let { name, fixpoint, .. }, Children::Two(value, body)) => Node::Let {
+                name: name.clone(),
+                value,
+                body,
+                fixpoint: *fixpoint,
+            },
+            _ => Node::Error,
+        })
+    }
+}
+
+struct MapBound<F> {
+    from: usize,
+    replace: F,
+    // How many binders enclose the current node within the term.
+    depth: usize,
+}
+
+impl<'t, F: FnMut(usize, usize) -> Term> Fold<'t, Term> for MapBound<F> {
+    type Output = Term;
+
+    fn visit(&mut self, term: &'t Term) -> Option<Term> {
+        if term.0.loose <= self.from + self.depth {
+            return Some(term.clone());
+        }
+        match &**term {
+            Node::Bound(index) => Some((self.replace)(*index, self.depth)),
+            _ => None,
+        }
+    }
+
+    fn descend(&mut self, term: &'t Term, branch: Branch) {
+        self.depth += usize::from(term.binds(branch));
+    }
+
+    fn ascend(&mut self, term: &'t Term, branch: Branch) {
+        self.depth -= usize::from(term.binds(branch));
+    }
+
+    fn build(&mut self, term: &'t Term, children: Children<Term>) -> Term {
+        term.rebuild(children)
+    }
+}
+
+// The binder a traversal enters on going down `branch` of `term`, if any.
+// A `letrec` also binds its value, so its binder is entered before the
+// value and left only after the body.
+fn entered(term: &Term, branch: Branch) -> Option<&str> {
+    match (&**term, branch) {
+        (Node::Lambda { name, .. }, Branch::Body)
+        | (
+            Node::Let {
+                name,
+                fixpoint: Some(_),
+                ..
+            },
+            Branch::Value,
+        )
+        | (
+            Node::Let {
+                name,
+                fixpoint: None,
+                ..
+            },
+            Branch::Body,
+        ) => Some(name),
+        _ => None,
+    }
+}
+
+// The binder a traversal leaves on coming back up `branch` of `term`.
+fn left(term: &Term, branch: Branch) -> Option<&str> {
+    match (&**term, branch) {
+        (Node::Lambda { name, .. } | Node::Let { name, .. }, Branch::Body) => Some(name),
+        _ => None,
+    }
+}
+
+// Reads a term back whose binders capture nothing, as `respelled` leaves it.
+struct Namer<'t> {
+    // The names of the enclosing binders, innermost last.
+    names: Vec<&'t str>,
+}
+
+impl<'t> Fold<'t, Term> for Namer<'t> {
+    type Output = AST;
+
+    fn visit(&mut self, term: &'t Term) -> Option<AST> {
+        match &**term {
+            Node::Bound(index) => Some(AST::Var(
+                self.names[self.names.len() - 1 - index].to_string(),
+            )),
+            Node::Free(name) => Some(AST::Var(name.clone())),
+            _ => None,
+        }
+    }
+
+    fn descend(&mut self, term: &'t Term, branch: Branch) {
+        if let Some(name) = entered(term, branch) {
+            self.names.push(name);
+        }
+    }
+
+    fn ascend(&mut self, term: &'t Term, branch: Branch) {
+        if left(term, branch).is_some() {
+            self.names.pop();
+        }
+    }
+
+    fn build(&mut self, term: &'t Term, children: Children<AST>) -> AST {
+        match (&**term, children) {
+            (Node::Lambda { name, .. }, Children::One(body)) => AST::Lambda {
+                param: name.clone(),
+                body: Box::new(body),
+            },
+            (Node::App(..), Children::Two(left, right)) => {
+                AST::App(Box::new(left), Box::new(right))
+            }
+            (Node::Let { name, fixpoint, .. }, Children::Two(value, body)) => AST::Let {
+                name: name.clone(),
+                value: Box::new(value),
+                body: Box::new(body),
+                fixpoint: *fixpoint,
+            },
+            _ => AST::Error,
+        }
+    }
+}
+
+// Finds the binders that capture a variable, in one pass. Binders are
+// numbered in the order they are entered. While a binder is in scope and
+// not yet found to capture anything, it is visible under its name, so a
+// variable captured by binders of its own name finds them at the top of
+// that name's stack.
+struct Captures<'t> {
+    outer: &'t [String],
+    // The binders around the current node, outermost first, by name and
+    // number.
+    scope: Vec<(&'t str, usize)>,
+    // For each name, the depths in `scope` of the visible binders with that
+    // name, innermost last.
+    visible: HashMap<&'t str, Vec<usize>, BuildHasherDefault<NameHasher>>,
+    // Whether each binder numbered so far captures a variable.
+    captured: Vec<bool>,
+}
+
+impl<'t> Fold<'t, Term> for Captures<'t> {
+    type Output = ();
+
+    fn visit(&mut self, term: &'t Term) -> Option<()> {
+        match &**term {
+            Node::Bound(index) if *index >= self.scope.len() => {
+                let outside = index - self.scope.len();
+                self.capture(&self.outer[self.outer.len() - 1 - outside], 0);
+            }
+            Node::Bound(index) => {
+                let depth = self.scope.len() - 1 - index;
+                let (name, binder) = self.scope[depth];
+                // A renamed binder's fresh name is never captured.
+                if !self.captured[binder] {
+                    self.capture(name, depth + 1);
+                }
+            }
+            Node::Free(name) => self.capture(name, 0),
+            _ => {}
+        }
+        None
+    }
+
+    fn descend(&mut self, term: &'t Term, branch: Branch) {
+        if let Some(name) = entered(term, branch) {
+            self.bind(name);
+        }
+    }
+
+    fn ascend(&mut self, term: &'t Term, branch: Branch) {
+        if left(term, branch).is_some() {
+            self.unbind();
+        }
+    }
+
+    fn build(&mut self, _: &'t Term, _: Children<()>) {}
+}
+
+impl<'t> Captures<'t> {
+    fn bind(&mut self, name: &'t str) {
+        self.visible.entry(name).or_default().push(self.scope.len());
+        self.scope.push((name, self.captured.len()));
+        self.captured.push(false);
+    }
+
+    fn unbind(&mut self) {
+        let (name, _) = self.scope.pop().expect("a binder in scope");
+        let depths = self.visible.get_mut(name).expect("a visible name");
+        if depths.last() == Some(&self.scope.len()) {
+            depths.pop();
+        }
+    }
+
+    // Every visible binder named `name` at `from` or deeper captures a
+    // reference to a variable of that name.
+    fn capture(&mut self, name: &str, from: usize) {
+        let Some(depths) = self.visible.get_mut(name) else {
+            return;
+        };
+        while let Some(&depth) = depths.last().filter(|&&depth| depth >= from) {
+            depths.pop();
+            self.captured[self.scope[depth].1] = true;
+        }
+    }
+}
+
+// Rebuilds a term, giving each binder `Captures` found a fresh name.
+struct Respeller<'r> {
+    captured: Vec<bool>,
+    // Every name in and around the term, and each fresh one chosen.
+    taken: HashSet<String>,
+    // The number of the next binder to be entered.
+    next: usize,
+    // The names chosen for the binders entered and not yet built, innermost
+    // last.
+    chosen: Vec<String>,
+    renamed: &'r mut Vec<Rename>,
+}
+
+impl<'t> Fold<'t, Term> for Respeller<'_> {
+    type Output = Term;
+
+    fn visit(&mut self, term: &'t Term) -> Option<Term> {
+        match &**term {
+            Node::Bound(_) | Node::Free(_) | Node::Error => Some(term.clone()),
+            _ => None,
+        }
+    }
+
+    fn descend(&mut self, term: &'t Term, branch: Branch) {
+        if let Some(name) = entered(term, branch) {
+            let chosen = self.choose(name);
+            self.chosen.push(chosen);
+        }
+    }
+
+    fn build(&mut self, term: &'t Term, children: Children<Term>) -> Term {
+        let node = match (&**term, children) {
+            (Node::Lambda { .. }, Children::One(body)) => Node::Lambda {
+                name: self.chosen.pop().expect("binder entered"),
+                body,
+            },
+            (Node::App(..), Children::Two(left, right)) => Node::App(left, right),
+            (Node::Let { fixpoint, .. }, Children::Two(value, body)) => Node::Let {
+                name: self.chosen.pop().expect("binder entered"),
+                value,
+                body,
+                fixpoint: *fixpoint,
+            },
+            _ => return term.clone(),
+        };
+        Term::new(node)
+    }
+}
+
+impl Respeller<'_> {
+    fn choose(&mut self, name: &str) -> String {
+        let binder = self.next;
+        self.next += 1;
+        if !self.captured[binder] {
+            return name.to_string();
+        }
+        let fresh = fresh_var(&self.taken, name);
+        self.taken.insert(fresh.clone());
+        self.renamed.push(Rename {
+            from: name.to_string(),
+            to: fresh.clone(),
+        });
+        fresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::{self, Reduction, Strategy};
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    fn parse_default(input: &str) -> AST {
+        parse(input, ParseOptions::default()).expect("the term parses")
+    }
+
+    // Contract the first redex of `input` and read the result back.
+    fn read_back(input: &str) -> (String, Vec<(String, String)>) {
+        let ast = parse_default(input);
+        let term = Term::from_ast(&ast);
+        let contraction =
+            strategy::step(&term, Strategy::NormalOrder, Reduction::Beta).expect("a redex");
+        let mut renamed = contraction.renamed;
+        let after = contraction.term.to_ast(&mut renamed);
+        let renamed = renamed.into_iter().map(|r| (r.from, r.to)).collect();
+        (ast_to_string(&after, PrintOptions::default()), renamed)
+    }
+
+    #[test]
+    fn read_back_keeps_names() {
+        // Unreduced terms come back exactly as written, shadowing included.
+        for input in ["λx.λy.x y z", "λx.λx.x", "λf.(λx.f (x x)) λx.f (x x)"] {
+            let ast = parse_default(input);
+            let mut renamed = Vec::new();
+            assert_eq!(Term::from_ast(&ast).to_ast(&mut renamed), ast);
+            assert!(renamed.is_empty());
+        }
+        let renames = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            let pairs = pairs.iter();
+            pairs
+                .map(|&(a, b)| (a.to_string(), b.to_string()))
+                .collect()
+        };
+        assert_eq!(read_back("(λx.λx.x) a"), ("λx.x".to_string(), renames(&[])));
+        // Only binders that would capture a free variable are renamed.
+        assert_eq!(
+            read_back("(λx.λy.λz.x) (λw.y)"),
+            ("λy1.λz.λw.y".to_string(), renames(&[("y", "y1")]))
+        );
+        // Or one bound outside the redex.
+        assert_eq!(
+            read_back("λy.(λx.λy.x) y"),
+            ("λy.λy1.y".to_string(), renames(&[("y", "y1")]))
+        );
+        // Fresh names avoid each other and the free variables.
+        assert_eq!(
+            read_back("(λx.λy.λy0.x y y0) (y y0)"),
+            (
+                "λy1.λy01.y y0 y1 y01".to_string(),
+                renames(&[("y", "y1"), ("y0", "y01")])
+            )
+        );
+    }
+
+    #[test]
+    fn steps_share_untouched_subterms() {
+        let numeral = parse_default("λf x.f (f (f x))");
+        let term = Term::from_ast(&parse_default("(λa.a) (λf x.f (f (f x))) y"));
+        assert_eq!(term.size(), 14);
+        let contraction =
+            strategy::step(&term, Strategy::NormalOrder, Reduction::Beta).expect("a redex");
+        // The numeral is the same node before and after.
+        let Node::App(function, _) = &*contraction.term else {
+            panic!("an application");
+        };
+        assert!(function.alpha_eq(&Term::from_ast(&numeral)));
+        let before = term.subterm(&[Branch::Function, Branch::Argument]);
+        assert!(Rc::ptr_eq(&before.expect("the argument").0, &function.0));
+        // Each copy of an argument at one depth is the same node.
+        let term = Term::from_ast(&parse_default("(λa.a a) (λf x.f (f (f x)))"));
+        let (_, reduct) = term.contract().expect("a β-redex");
+        let Node::App(left, right) = &*reduct else {
+            panic!("an application");
+        };
+        assert!(Rc::ptr_eq(&left.0, &right.0));
+    }
+}
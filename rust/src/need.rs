@@ -0,0 +1,559 @@
+//! Call-by-need evaluation: call-by-name, but with every argument shared
+//! through a thunk on a heap, which is overwritten with its value the first
+//! time it is forced.
+//!
+//! Terms are never copied. The evaluator is an environment machine (after
+//! Sestoft's lazy machine) on the nameless core, whose control is always a
+//! subterm of the input, paired with an environment mapping its indices to
+//! heap cells. A stack holds the arguments waiting to be applied and the
+//! thunks waiting to be updated. Evaluation stops at weak head normal form;
+//! the result is then read back into a term, evaluating under binders on
+//! demand, so that the final answer is the normal form. Bound variables in
+//! the result keep their names unless that would capture a variable.
+//!
+//! In the trace, `#n` stands for heap cell `n`, so a cell that appears in
+//! several places is shared rather than copied. Each entry of the trace
+//! prints the whole control and stack, so it is only kept when asked for.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::env;
+use super::nameless::{Node, Term};
+use super::normalize::Outcome;
+use super::AST;
+
+/// What a thunk cost, compared with call-by-name.
+pub struct ThunkStats {
+    pub id: usize,
+    /// The suspended term, as it was when the thunk was created, if the
+    /// evaluation was traced.
+    pub term: Option<AST>,
+    /// How many times the thunk was evaluated: never more than once.
+    pub forced: usize,
+    /// How many times its value was used. Call-by-name evaluates a separate
+    /// copy of the argument every time.
+    pub copies: usize,
+}
+
+/// A rule of the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Rule {
+    /// Suspend the argument of an application in a new thunk (or reuse the
+    /// cell of a variable) and evaluate the function.
+    Push,
+    /// Apply an abstraction to the argument on top of the stack, binding its
+    /// variable to the argument's cell.
+    Beta,
+    /// A variable's cell is a thunk: evaluate it, then update the cell.
+    Force,
+    /// A variable's cell already holds a value: use it without evaluating
+    /// anything.
+    Reuse,
+    /// Overwrite a forced thunk with the value it evaluated to.
+    Update,
+    /// Bind a `let` or `letrec` to a new thunk.
+    Let,
+    /// Read back under a binder, binding its variable to a fresh name.
+    Enter,
+}
+
+/// One transition of the machine.
+pub struct TraceEntry {
+    pub rule: Rule,
+    /// The term under evaluation after the transition, with heap cells as
+    /// `#n`.
+    pub control: AST,
+    /// Top of the stack first: a cell waiting to be applied, or an update.
+    pub stack: Vec<String>,
+    /// The cell the transition allocated or updated, with its new contents.
+    pub cell: Option<(usize, AST)>,
+}
+
+pub struct Evaluation {
+    /// The normal form, if it was reached.
+    pub term: Option<AST>,
+    pub steps: usize,
+    pub outcome: Outcome,
+    pub thunks: Vec<ThunkStats>,
+    /// Empty unless the evaluation was traced.
+    pub trace: Vec<TraceEntry>,
+}
+
+/// Evaluate `ast` to normal form by need, taking at most `max_steps`
+/// machine transitions. With `trace`, every transition is recorded, along
+/// with the term of every thunk.
+pub fn evaluate(ast: &AST, max_steps: usize, trace: bool) -> Evaluation {
+    let term = Term::from_ast(ast);
+    let mut machine = Machine {
+        heap: Vec::new(),
+        stats: Vec::new(),
+        trace: trace.then(Vec::new),
+        steps: 0,
+        max_steps,
+    };
+    let result = machine
+        .run(Control::Eval(&term, None), Vec::new())
+        .and_then(|value| machine.read_back(value));
+    let (term, outcome) = match result {
+        Ok(term) => (Some(term.to_ast(&mut Vec::new())), Outcome::NormalForm),
+        Err(stop) => (None, stop),
+    };
+    Evaluation {
+        term,
+        steps: machine.steps,
+        outcome,
+        thunks: machine.stats.into_iter().flatten().collect(),
+        trace: machine.trace.unwrap_or_default(),
+    }
+}
+
+// Each variable is bound to a heap cell.
+type Env<'a> = env::Env<&'a str, usize>;
+
+// Cells hold their environments on the heap instead.
+impl env::Value<&str> for usize {}
+
+fn lookup(env: &Env, index: usize) -> usize {
+    env::bindings(env)
+        .nth(index)
+        .expect("bound in the environment")
+        .value
+}
+
+#[derive(Clone)]
+enum Value<'a> {
+    // An abstraction (a `Node::Lambda`) with the environment it was built
+    // in.
+    Closure(&'a Term, Env<'a>),
+    // A variable that is not bound to a cell applied to the cells of its
+    // arguments.
+    Neutral(Head<'a>, Vec<usize>),
+}
+
+#[derive(Clone)]
+enum Head<'a> {
+    Free(String),
+    // The variable of the binder read back this many binders in, with its
+    // name.
+    Binder(usize, &'a str),
+}
+
+enum Cell<'a> {
+    Thunk(&'a Term, Env<'a>),
+    // A thunk being forced. Needing its value again means it depends on
+    // itself.
+    BlackHole(&'a Term, Env<'a>),
+    Value(Value<'a>),
+}
+
+enum Control<'a> {
+    Eval(&'a Term, Env<'a>),
+    Value(Value<'a>),
+}
+
+enum Frame {
+    Arg(usize),
+    Update(usize),
+}
+
+struct Machine<'a> {
+    heap: Vec<Cell<'a>>,
+    // For each cell that holds a thunk.
+    stats: Vec<Option<ThunkStats>>,
+    // `None` unless tracing.
+    trace: Option<Vec<TraceEntry>>,
+    steps: usize,
+    max_steps: usize,
+}
+
+impl<'a> Machine<'a> {
+    fn alloc(&mut self, cell: Cell<'a>) -> usize {
+        let thunk = match &cell {
+            Cell::Thunk(term, env) => Some(ThunkStats {
+                id: self.heap.len(),
+                term: self.trace.is_some().then(|| display(term, env)),
+                forced: 0,
+                copies: 0,
+            }),
+            _ => None,
+        };
+        self.heap.push(cell);
+        self.stats.push(thunk);
+        self.heap.len() - 1
+    }
+
+    fn tick(&mut self) -> Result<(), Outcome> {
+        if self.steps == self.max_steps {
+            return Err(Outcome::BudgetExhausted);
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn record(&mut self, rule: Rule, control: &Control<'a>, stack: &[Frame], cell: Option<usize>) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        let control = match control {
+            Control::Eval(term, env) => display(term, env),
+            Control::Value(value) => display_value(value),
+        };
+        let stack = stack
+            .iter()
+            .rev()
+            .map(|frame| match frame {
+                Frame::Arg(cell) => cell_name(*cell),
+                Frame::Update(cell) => format!("update #{}", cell),
+            })
+            .collect();
+        let cell = cell.map(|id| {
+            let contents = match &self.heap[id] {
+                Cell::Thunk(term, env) | Cell::BlackHole(term, env) => display(term, env),
+                Cell::Value(value) => display_value(value),
+            };
+            (id, contents)
+        });
+        trace.push(TraceEntry {
+            rule,
+            control,
+            stack,
+            cell,
+        });
+    }
+
+    // Run the machine until the control is a value and no argument is left
+    // to apply to it.
+    fn run(
+        &mut self,
+        mut control: Control<'a>,
+        mut stack: Vec<Frame>,
+    ) -> Result<Value<'a>, Outcome> {
+        loop {
+            let (rule, cell) = match control {
+                Control::Eval(term, env) => match &**term {
+                    &Node::Bound(index) => {
+                        let cell = lookup(&env, index);
+                        self.tick()?;
+                        if let Some(stats) = &mut self.stats[cell] {
+                            stats.copies += 1;
+                        }
+                        match &self.heap[cell] {
+                            Cell::Value(value) => {
+                                control = Control::Value(value.clone());
+                                (Rule::Reuse, None)
+                            }
+                            Cell::BlackHole(..) => return Err(Outcome::BlackHole),
+                            &Cell::Thunk(term, ref env) => {
+                                let env = env.clone();
+                                self.heap[cell] = Cell::BlackHole(term, env.clone());
+                                if let Some(stats) = &mut self.stats[cell] {
+                                    stats.forced += 1;
+                                }
+                                stack.push(Frame::Update(cell));
+                                control = Control::Eval(term, env);
+                                (Rule::Force, None)
+                            }
+                        }
+                    }
+                    Node::Free(name) => {
+                        control =
+                            Control::Value(Value::Neutral(Head::Free(name.clone()), Vec::new()));
+                        continue;
+                    }
+                    Node::Error => {
+                        control = Control::Value(Value::Neutral(
+                            Head::Free(super::ERROR_NAME.to_string()),
+                            Vec::new(),
+                        ));
+                        continue;
+                    }
+                    Node::Lambda { .. } => {
+                        control = Control::Value(Value::Closure(term, env));
+                        continue;
+                    }
+                    Node::App(function, argument) => {
+                        self.tick()?;
+                        let (cell, allocated) = match &**argument {
+                            // A variable already names a cell, which is shared.
+                            &Node::Bound(index) => (lookup(&env, index), None),
+                            Node::Free(name) => {
+                                let cell = self.alloc(Cell::Value(Value::Neutral(
+                                    Head::Free(name.clone()),
+                                    Vec::new(),
+                                )));
+                                (cell, Some(cell))
+                            }
+                            _ => {
+                                let cell = self.alloc(Cell::Thunk(argument, env.clone()));
+                                (cell, Some(cell))
+                            }
+                        };
+                        stack.push(Frame::Arg(cell));
+                        control = Control::Eval(function, env);
+                        (Rule::Push, allocated)
+                    }
+                    Node::Let {
+                        name,
+                        value,
+                        body,
+                        fixpoint,
+                    } => {
+                        self.tick()?;
+                        // The cell about to be allocated.
+                        let cell = self.heap.len();
+                        let inner = env::bind(&env, name.as_str(), cell);
+                        // The value of a `letrec` sees its own binding.
+                        let scope = match fixpoint {
+                            Some(_) => inner.clone(),
+                            None => env,
+                        };
+                        self.alloc(Cell::Thunk(value, scope));
+                        control = Control::Eval(body, inner);
+                        (Rule::Let, Some(cell))
+                    }
+                },
+                Control::Value(value) => match (stack.pop(), value) {
+                    (None, value) => return Ok(value),
+                    (Some(Frame::Update(cell)), value) => {
+                        self.tick()?;
+                        self.heap[cell] = Cell::Value(value.clone());
+                        control = Control::Value(value);
+                        (Rule::Update, Some(cell))
+                    }
+                    (Some(Frame::Arg(cell)), Value::Closure(lambda, env)) => {
+                        self.tick()?;
+                        let Node::Lambda { name, body } = &**lambda else {
+                            unreachable!("closures hold abstractions")
+                        };
+                        control = Control::Eval(body, env::bind(&env, name.as_str(), cell));
+                        (Rule::Beta, None)
+                    }
+                    (Some(Frame::Arg(cell)), Value::Neutral(head, mut args)) => {
+                        // Nothing to do but collect the argument.
+                        args.push(cell);
+                        control = Control::Value(Value::Neutral(head, args));
+                        continue;
+                    }
+                },
+            };
+            self.record(rule, &control, &stack, cell);
+        }
+    }
+
+    // The value of a cell, forcing it if need be.
+    fn force(&mut self, cell: usize) -> Result<Value<'a>, Outcome> {
+        if let Some(stats) = &mut self.stats[cell] {
+            stats.copies += 1;
+        }
+        match &self.heap[cell] {
+            Cell::Value(value) => Ok(value.clone()),
+            Cell::BlackHole(..) => Err(Outcome::BlackHole),
+            &Cell::Thunk(term, ref env) => {
+                let env = env.clone();
+                self.heap[cell] = Cell::BlackHole(term, env.clone());
+                if let Some(stats) = &mut self.stats[cell] {
+                    stats.forced += 1;
+                }
+                self.run(Control::Eval(term, env), vec![Frame::Update(cell)])
+            }
+        }
+    }
+
+    // Turn a value back into a term, evaluating inside it as far as it goes.
+    // Binders keep their names, and `to_ast` renames those that capture.
+    fn read_back(&mut self, value: Value<'a>) -> Result<Term, Outcome> {
+        // How many binders enclose the value being read back.
+        let mut depth = 0;
+        // The cells whose values are being read back, with the step count
+        // when each was reached. Reaching one again inside its own value
+        // without a step in between means the value contains itself, as in
+        // `letrec x = f x in x`, so reading it back would never finish.
+        let mut reading = HashMap::new();
+        let mut tasks = vec![ReadBack::Value(value)];
+        let mut terms = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                ReadBack::Value(Value::Closure(lambda, env)) => {
+                    let Node::Lambda { name, body } = &**lambda else {
+                        unreachable!("closures hold abstractions")
+                    };
+                    self.tick()?;
+                    let cell = self.alloc(Cell::Value(Value::Neutral(
+                        Head::Binder(depth, name),
+                        Vec::new(),
+                    )));
+                    let env = env::bind(&env, name.as_str(), cell);
+                    self.record(
+                        Rule::Enter,
+                        &Control::Eval(body, env.clone()),
+                        &[],
+                        Some(cell),
+                    );
+                    let value = self.run(Control::Eval(body, env), Vec::new())?;
+                    depth += 1;
+                    tasks.push(ReadBack::Lambda(name));
+                    tasks.push(ReadBack::Value(value));
+                }
+                ReadBack::Value(Value::Neutral(head, args)) => {
+                    terms.push(Term::new(match head {
+                        Head::Free(name) => Node::Free(name),
+                        Head::Binder(level, _) => Node::Bound(depth - 1 - level),
+                    }));
+                    for &cell in args.iter().rev() {
+                        tasks.push(ReadBack::Apply);
+                        tasks.push(ReadBack::Force(cell));
+                    }
+                }
+                ReadBack::Force(cell) => {
+                    let reached = reading.insert(cell, self.steps);
+                    if reached == Some(self.steps) {
+                        return Err(Outcome::BlackHole);
+                    }
+                    let value = self.force(cell)?;
+                    tasks.push(ReadBack::Read(cell, reached));
+                    tasks.push(ReadBack::Value(value));
+                }
+                ReadBack::Read(cell, reached) => match reached {
+                    Some(steps) => {
+                        reading.insert(cell, steps);
+                    }
+                    None => {
+                        reading.remove(&cell);
+                    }
+                },
+                ReadBack::Apply => {
+                    let argument = terms.pop().expect("argument read back");
+                    let function = terms.pop().expect("function read back");
+                    terms.push(Term::new(Node::App(function, argument)));
+                }
+                ReadBack::Lambda(name) => {
+                    depth -= 1;
+                    let body = terms.pop().expect("body read back");
+                    terms.push(Term::new(Node::Lambda {
+                        name: name.to_string(),
+                        body,
+                    }));
+                }
+            }
+        }
+        Ok(terms.pop().expect("value read back"))
+    }
+}
+
+// What is left to do in reading back a value.
+enum ReadBack<'a> {
+    Value(Value<'a>),
+    // Read back the value of a cell, forcing it if need be.
+    Force(usize),
+    // The value of a cell has been read back. Its entry in `reading` goes
+    // back to what it was.
+    Read(usize, Option<usize>),
+    // Apply the term read back before the last one to the last one.
+    Apply,
+    // Abstract the last term read back over a binder with this name.
+    Lambda(&'a str),
+}
+
+fn cell_name(cell: usize) -> String {
+    format!("#{}", cell)
+}
+
+// `term` with each variable bound in `env` replaced by its cell.
+fn display(term: &Term, env: &Env) -> AST {
+    let cells: Vec<usize> = env::bindings(env)
+        .take(term.loose())
+        .map(|binding| binding.value)
+        .collect();
+    term.close(|index| Term::new(Node::Free(cell_name(cells[index]))))
+        .to_ast(&mut Vec::new())
+}
+
+fn display_value(value: &Value) -> AST {
+    match value {
+        Value::Closure(lambda, env) => display(lambda, env),
+        Value::Neutral(head, args) => {
+            let head = match head {
+                Head::Free(name) => name,
+                Head::Binder(_, name) => *name,
+            };
+            args.iter().fold(AST::Var(head.to_string()), |term, &cell| {
+                AST::App(Box::new(term), Box::new(AST::Var(cell_name(cell))))
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    fn print(ast: &AST) -> String {
+        ast_to_string(ast, PrintOptions::default())
+    }
+
+    #[test]
+    fn thunks_are_forced_once() {
+        let ast = parse("MULT = λm.λn.λf.m (n f); MULT 2 3", ParseOptions::default()).unwrap();
+        let traced = evaluate(&ast, 1000, true);
+        assert_eq!(traced.outcome, Outcome::NormalForm);
+        assert_eq!(
+            traced.term.as_ref().map(print).as_deref(),
+            Some("λf.λx.f (f (f (f (f (f x)))))")
+        );
+        assert!(traced.thunks.iter().all(|thunk| thunk.forced == 1));
+        // `2` uses its `f`, which is `n f`, twice: by name it would be
+        // evaluated twice.
+        let shared: Vec<_> = traced
+            .thunks
+            .iter()
+            .filter(|thunk| thunk.copies > thunk.forced)
+            .map(|thunk| (thunk.term.as_ref().map(print), thunk.forced, thunk.copies))
+            .collect();
+        assert_eq!(shared, [(Some("#0 #2".to_string()), 1, 2)]);
+        assert_eq!(traced.trace.len(), traced.steps);
+
+        // Untraced, the same work is counted but no terms are kept.
+        let untraced = evaluate(&ast, 1000, false);
+        assert_eq!(untraced.steps, traced.steps);
+        assert!(untraced.trace.is_empty());
+        let counts = |evaluation: &Evaluation| -> Vec<_> {
+            let thunks = evaluation.thunks.iter();
+            thunks
+                .map(|thunk| (thunk.id, thunk.forced, thunk.copies))
+                .collect()
+        };
+        assert_eq!(counts(&untraced), counts(&traced));
+        assert!(untraced.thunks.iter().all(|thunk| thunk.term.is_none()));
+
+        let exhausted = evaluate(&ast, 10, false);
+        assert_eq!(
+            (exhausted.outcome, exhausted.term.is_none()),
+            (Outcome::BudgetExhausted, true)
+        );
+    }
+
+    #[test]
+    fn read_back_and_letrec() {
+        let options = ParseOptions {
+            keep_let: true,
+            ..ParseOptions::default()
+        };
+        let evaluate = |input: &str| {
+            let evaluation = evaluate(&parse(input, options).unwrap(), 100, false);
+            (evaluation.term.as_ref().map(print), evaluation.outcome)
+        };
+        // Binders keep their names unless they would capture.
+        assert_eq!(
+            evaluate("λy.(λx.λy.x y) y"),
+            (Some("λy.λy1.y y1".to_string()), Outcome::NormalForm)
+        );
+        assert_eq!(
+            evaluate("letrec f = λb.b (λx.x) (f λt e.t) in f λt e.e"),
+            (Some("λx.x".to_string()), Outcome::NormalForm)
+        );
+        assert_eq!(evaluate("letrec x = f x in x"), (None, Outcome::BlackHole));
+    }
+}
@@ -0,0 +1,360 @@
+//! Reducing a term as far as its strategy goes, within a step budget.
+//!
+//! Reduction is deterministic, so once it comes back to a term it has seen
+//! before (up to α-equivalence) it will go round the same cycle forever.
+//! Such cycles are found with Brent's algorithm, in constant memory, and
+//! reported instead of using up the budget. Terms that grow without bound
+//! never repeat; for those, the most recent steps are examined for a
+//! repeating pattern once the budget runs out. A term can grow in a
+//! pattern for a while and still reach a normal form, so growth alone
+//! never stops reduction early.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+use serde::Serialize;
+
+use super::nameless::{Node, Term};
+use super::strategy::{self, Branch, Contraction, Reduction, Step};
+use super::traverse::{fold, Children, Fold};
+use super::{Strategy, AST};
+
+/// Why normalization stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Outcome {
+    /// No step applies: the term is in the normal form of the strategy
+    /// (weak head normal form for `WeakHead`, and so on).
+    NormalForm,
+    /// `max_steps` steps were taken and another one was still possible.
+    BudgetExhausted,
+    /// The term is α-equivalent to the one `period` steps earlier, so
+    /// reduction would repeat those steps forever.
+    Cycle { period: usize },
+    /// `max_steps` steps were taken, and the last of them contracted the
+    /// same redexes over and over, every `period` steps, with the term
+    /// growing each time. This is a heuristic: the term very likely has no
+    /// normal form.
+    Growing { period: usize },
+    /// Call-by-need evaluation needed the value of a thunk in order to
+    /// compute that same value, or its normal form contains itself, so it
+    /// can never finish.
+    BlackHole,
+}
+
+pub struct Normalization {
+    pub term: AST,
+    pub steps: usize,
+    pub outcome: Outcome,
+}
+
+/// Reduce `ast` until no step applies, it cycles, or `max_steps` steps have
+/// been taken.
+///
+/// The term stays nameless throughout, with every node a step leaves alone
+/// shared with the term before it, and only the result is read back.
+pub fn normalize(
+    ast: AST,
+    strategy: Strategy,
+    reduction: Reduction,
+    max_steps: usize,
+) -> Normalization {
+    let term = Term::from_ast(&ast);
+    let (term, steps, outcome) = reduce(term, strategy, reduction, max_steps, |contraction| {
+        contraction.term
+    });
+    Normalization {
+        term: match steps {
+            0 => ast,
+            _ => term.to_ast(&mut Vec::new()),
+        },
+        steps,
+        outcome,
+    }
+}
+
+/// `normalize`, calling `on_step` with the term before each step and the
+/// step itself.
+pub fn normalize_with(
+    ast: AST,
+    strategy: Strategy,
+    reduction: Reduction,
+    max_steps: usize,
+    mut on_step: impl FnMut(&AST, &Step),
+) -> Normalization {
+    let term = Term::from_ast(&ast);
+    let mut before = ast;
+    let (_, steps, outcome) = reduce(term, strategy, reduction, max_steps, |contraction| {
+        let step = Step::new(&before, &contraction);
+        on_step(&before, &step);
+        before = step.term;
+        contraction.term
+    });
+    Normalization {
+        term: before,
+        steps,
+        outcome,
+    }
+}
+
+// Reduce `term` as `normalize` describes, taking what `next` makes of each
+// contraction as the next term.
+fn reduce(
+    mut term: Term,
+    strategy: Strategy,
+    reduction: Reduction,
+    max_steps: usize,
+    mut next: impl FnMut(Contraction) -> Term,
+) -> (Term, usize, Outcome) {
+    let mut checkpoint = Checkpoint::new(&term);
+    let mut history = VecDeque::new();
+    let mut steps = 0;
+    while let Some(choice) = strategy::choose(&term, strategy, reduction) {
+        if steps == max_steps {
+            let outcome = match growth(&history) {
+                Some(period) => Outcome::Growing { period },
+                None => Outcome::BudgetExhausted,
+            };
+            return (term, steps, outcome);
+        }
+        let redex = redex_hash(&term, choice.path());
+        let contraction = choice.apply(&term);
+        let size = contraction.term.size();
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back((redex, size));
+        term = next(contraction);
+        steps += 1;
+        if let Some(period) = checkpoint.check(&term) {
+            return (term, steps, Outcome::Cycle { period });
+        }
+    }
+    (term, steps, Outcome::NormalForm)
+}
+
+// How many of the most recent steps are kept to look for growth.
+const HISTORY: usize = 1024;
+
+// A term from earlier in the sequence to compare the current one with. It
+// moves to the current term whenever the distance to it reaches the next
+// power of two, so a cycle of period `n` is found within a few times `n`
+// steps of being entered.
+struct Checkpoint {
+    term: Term,
+    distance: usize,
+    power: usize,
+}
+
+impl Checkpoint {
+    fn new(term: &Term) -> Self {
+        Checkpoint {
+            term: term.clone(),
+            distance: 0,
+            power: 1,
+        }
+    }
+
+    // The period, if `term` repeats the checkpoint.
+    fn check(&mut self, term: &Term) -> Option<usize> {
+        self.distance += 1;
+        if term.alpha_eq(&self.term) {
+            return Some(self.distance);
+        }
+        if self.distance == self.power {
+            *self = Checkpoint {
+                term: term.clone(),
+                distance: 0,
+                power: 2 * self.power,
+            };
+        }
+        None
+    }
+}
+
+// Identifies the redex at the end of `path`, up to α-equivalence. Variables
+// bound outside it are identified by the names of their binders.
+fn redex_hash(before: &Term, path: &[Branch]) -> u64 {
+    let mut hasher = RedexHasher {
+        outside: before.binders_along(path),
+        depth: 0,
+        state: DefaultHasher::new(),
+    };
+    if let Some(redex) = before.subterm(path) {
+        fold(redex, &mut hasher);
+    }
+    hasher.state.finish()
+}
+
+struct RedexHasher {
+    outside: Vec<String>,
+    // How many binders within the redex enclose the current node.
+    depth: usize,
+    state: DefaultHasher,
+}
+
+impl Fold<'_, Term> for RedexHasher {
+    type Output = ();
+
+    fn visit(&mut self, term: &Term) -> Option<()> {
+        if term.loose() <= self.depth {
+            // Nothing inside is bound outside the redex.
+            (6, term.alpha_hash()).hash(&mut self.state);
+            return Some(());
+        }
+        match &**term {
+            // The same as a free variable of that name.
+            Node::Bound(index) if *index >= self.depth => {
+                let outside = index - self.depth;
+                (0, &self.outside[self.outside.len() - 1 - outside]).hash(&mut self.state)
+            }
+            Node::Bound(index) => (1, index).hash(&mut self.state),
+            Node::Free(name) => (0, name).hash(&mut self.state),
+            Node::Error => 2.hash(&mut self.state),
+            Node::Lambda { .. } => 3.hash(&mut self.state),
+            Node::App(..) => 4.hash(&mut self.state),
+            Node::Let { fixpoint, .. } => (5, fixpoint).hash(&mut self.state),
+        }
+        None
+    }
+
+    fn descend(&mut self, term: &Term, branch: Branch) {
+        self.depth += usize::from(term.binds(branch));
+    }
+
+    fn ascend(&mut self, term: &Term, branch: Branch) {
+        self.depth -= usize::from(term.binds(branch));
+    }
+
+    fn build(&mut self, _: &Term, _: Children<()>) {}
+}
+
+// The shortest period with which the last three periods' worth of steps
+// contracted the same redexes in the same order while the term grew from
+// one period to the next.
+fn growth(history: &VecDeque<(u64, usize)>) -> Option<usize> {
+    let n = history.len();
+    (1..=n / 3).find(|&period| {
+        let same = (0..2 * period).all(|i| history[n - 1 - i].0 == history[n - 1 - i - period].0);
+        let last = history[n - 1].1;
+        let before = history[n - 1 - period].1;
+        let earlier = history[n - 1 - 2 * period].1;
+        same && earlier < before && before < last
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::Branch;
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    fn parse_default(input: &str) -> AST {
+        parse(input, ParseOptions::default()).expect("the term parses")
+    }
+
+    fn run(input: &str, max_steps: usize) -> (String, usize, Outcome) {
+        let result = normalize(
+            parse_default(input),
+            Strategy::NormalOrder,
+            Reduction::Beta,
+            max_steps,
+        );
+        let term = ast_to_string(&result.term, PrintOptions::default());
+        (term, result.steps, result.outcome)
+    }
+
+    #[test]
+    fn step_budget() {
+        // 2 squared.
+        let input = "(λn f.n (n f)) (λf x.f (f x))";
+        let (term, steps, outcome) = run(input, 100);
+        assert_eq!(
+            (term.as_str(), outcome),
+            ("λf.λx.f (f (f (f x)))", Outcome::NormalForm)
+        );
+        // Exactly enough steps, and one too few.
+        assert_eq!(run(input, steps).2, Outcome::NormalForm);
+        let (term, taken, outcome) = run(input, steps - 1);
+        assert_eq!((taken, outcome), (steps - 1, Outcome::BudgetExhausted));
+        assert_ne!(term, "λf.λx.f (f (f (f x)))");
+        // A normal form takes no steps and comes back as it was.
+        assert_eq!(
+            run("λx.x y", 0),
+            ("λx.x y".to_string(), 0, Outcome::NormalForm)
+        );
+    }
+
+    #[test]
+    fn renames_in_the_trace() {
+        let trace = |input: &str| {
+            let mut steps = Vec::new();
+            let ast = parse_default(input);
+            normalize_with(
+                ast,
+                Strategy::NormalOrder,
+                Reduction::Beta,
+                10,
+                |_, step| {
+                    let term = ast_to_string(&step.term, PrintOptions::default());
+                    let renamed: Vec<_> = step
+                        .renamed
+                        .iter()
+                        .map(|rename| format!("{}→{}", rename.from, rename.to))
+                        .collect();
+                    steps.push((step.path.clone(), term, renamed));
+                },
+            );
+            steps
+        };
+        let rename = |renames: &[&str]| renames.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            trace("(λx y.x y) y (λy.y)"),
+            [
+                (
+                    vec![Branch::Function],
+                    "(λy1.y y1) λy.y".to_string(),
+                    rename(&["y→y1"])
+                ),
+                // The renamed binder keeps its new name.
+                (vec![], "y λy.y".to_string(), rename(&[])),
+            ]
+        );
+        assert_eq!(
+            trace("(λx.λy.λy1.x y y1) (y y1)"),
+            [(
+                vec![],
+                "λy2.λy11.y y1 y2 y11".to_string(),
+                rename(&["y→y2", "y1→y11"])
+            )]
+        );
+    }
+
+    #[test]
+    fn cycles_and_growth() {
+        let omega = "(λx.x x) λx.x x";
+        let (term, steps, outcome) = run(omega, 1000);
+        assert_eq!(outcome, Outcome::Cycle { period: 1 });
+        assert_eq!((term.as_str(), steps), (omega, 1));
+        // `Y g` only ever grows: `g (Y' g)`, `g (g (Y' g))`, ... This is
+        // reported when the budget runs out.
+        let (term, steps, outcome) = run("(λf.(λx.f (x x)) (λx.f (x x))) g", 1000);
+        assert_eq!(outcome, Outcome::Growing { period: 1 });
+        assert_eq!(steps, 1000);
+        assert!(term.starts_with("g (g (g ("));
+        // Each argument grows when contracted, the same redex every step,
+        // but there are only four of them.
+        let twice = "(λx.x x) (a b c)";
+        let input = format!("f ({twice}) ({twice}) ({twice}) ({twice})");
+        let (term, steps, outcome) = run(&input, 1000);
+        assert_eq!(outcome, Outcome::NormalForm);
+        assert_eq!(steps, 4);
+        assert_eq!(
+            term,
+            "f (a b c (a b c)) (a b c (a b c)) (a b c (a b c)) (a b c (a b c))"
+        );
+        // A budget that runs out on a term with a normal form is just that.
+        let (_, _, outcome) = run("(λn f.n (n f)) (λf x.f (f x))", 2);
+        assert_eq!(outcome, Outcome::BudgetExhausted);
+    }
+}
@@ -0,0 +1,227 @@
+//! Numeric literals and the encodings they desugar to.
+
+use serde::Deserialize;
+
+use super::AST;
+
+/// How a numeric literal is represented as a term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Encoding {
+    /// `n = λf.λx.fⁿ x`: a numeral is its own iterator.
+    #[default]
+    Church,
+    /// `0 = λs.λz.z`, `n+1 = λs.λz.s n`: constant-time predecessor.
+    Scott,
+    /// `0 = λs.λz.z`, `n+1 = λs.λz.s n (n s z)`: both of the above at once.
+    Parigot,
+}
+
+impl Encoding {
+    /// The largest literal accepted. Anything bigger is rejected rather than
+    /// building a huge term; a Parigot numeral holds two copies of its
+    /// predecessor, so it doubles in size with every increment.
+    pub fn limit(self) -> u64 {
+        match self {
+            Encoding::Church | Encoding::Scott => 1000,
+            Encoding::Parigot => 16,
+        }
+    }
+}
+
+fn var(name: &str) -> Box<AST> {
+    Box::new(AST::Var(name.to_string()))
+}
+
+fn app(left: Box<AST>, right: Box<AST>) -> Box<AST> {
+    Box::new(AST::App(left, right))
+}
+
+// λa.λb.body
+fn abstraction(a: &str, b: &str, body: Box<AST>) -> AST {
+    AST::Lambda {
+        param: a.to_string(),
+        body: Box::new(AST::Lambda {
+            param: b.to_string(),
+            body,
+        }),
+    }
+}
+
+pub fn encode(n: u64, encoding: Encoding) -> AST {
+    match encoding {
+        Encoding::Church => {
+            let mut body = var("x");
+            for _ in 0..n {
+                body = app(var("f"), body);
+            }
+            abstraction("f", "x", body)
+        }
+        Encoding::Scott => {
+            let mut numeral = abstraction("s", "z", var("z"));
+            for _ in 0..n {
+                numeral = abstraction("s", "z", app(var("s"), Box::new(numeral)));
+            }
+            numeral
+        }
+        Encoding::Parigot => {
+            let mut numeral = abstraction("s", "z", var("z"));
+            for _ in 0..n {
+                let predecessor = Box::new(numeral);
+                let recursion = app(app(predecessor.clone(), var("s")), var("z"));
+                numeral = abstraction("s", "z", app(app(var("s"), predecessor), recursion));
+            }
+            numeral
+        }
+    }
+}
+
+/// Recognise a numeral in the given encoding, up to the names of its binders.
+pub fn decode(ast: &AST, encoding: Encoding) -> Option<u64> {
+    let (s, z, body) = binders(ast)?;
+    match encoding {
+        Encoding::Church => {
+            let mut n = 0;
+            let mut body = body;
+            while let AST::App(left, right) = body {
+                if !is_var(left, s) {
+                    return None;
+                }
+                n += 1;
+                body = right;
+            }
+            is_var(body, z).then_some(n)
+        }
+        Encoding::Scott => {
+            let mut n = 0;
+            let (mut s, mut z, mut body) = (s, z, body);
+            while let AST::App(left, predecessor) = body {
+                if !is_var(left, s) {
+                    return None;
+                }
+                n += 1;
+                (s, z, body) = binders(predecessor)?;
+            }
+            is_var(body, z).then_some(n)
+        }
+        Encoding::Parigot => {
+            // The second copy of each predecessor, with the number it must
+            // be, is checked once the first has been read.
+            let mut copies = Vec::new();
+            let n = parigot(ast, &mut copies)?;
+            while let Some((copy, expected)) = copies.pop() {
+                if parigot(copy, &mut copies)? != expected {
+                    return None;
+                }
+            }
+            Some(n)
+        }
+    }
+}
+
+// Read `λs.λz.s p (p' s z)` down the chain of first copies `p`, adding each
+// `p'` to `copies` with the number it must also decode to.
+fn parigot<'a>(ast: &'a AST, copies: &mut Vec<(&'a AST, u64)>) -> Option<u64> {
+    let mut seconds = Vec::new();
+    let mut numeral = ast;
+    loop {
+        let (s, z, body) = binders(numeral)?;
+        let AST::App(left, recursion) = body else {
+            if !is_var(body, z) {
+                return None;
+            }
+            break;
+        };
+        match (&**left, &**recursion) {
+            (AST::App(head, predecessor), AST::App(inner, arg_z))
+                if is_var(head, s) && is_var(arg_z, z) =>
+            {
+                match &**inner {
+                    AST::App(again, arg_s) if is_var(arg_s, s) => seconds.push(&**again),
+                    _ => return None,
+                }
+                numeral = predecessor;
+            }
+            _ => return None,
+        }
+    }
+    let n = seconds.len() as u64;
+    copies.extend((1..=n).rev().zip(seconds).map(|(m, again)| (again, m - 1)));
+    Some(n)
+}
+
+// Split `λa.λb.body` into its parts; the binders must be distinct.
+fn binders(ast: &AST) -> Option<(&str, &str, &AST)> {
+    match ast {
+        AST::Lambda { param: a, body } => match &**body {
+            AST::Lambda { param: b, body } if a != b => Some((a, b, body)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_var(ast: &AST, name: &str) -> bool {
+    matches!(ast, AST::Var(v) if v == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    const ENCODINGS: [Encoding; 3] = [Encoding::Church, Encoding::Scott, Encoding::Parigot];
+
+    #[test]
+    fn encodings_round_trip() {
+        for encoding in ENCODINGS {
+            for n in 0..=encoding.limit() {
+                let numeral = encode(n, encoding);
+                assert_eq!(decode(&numeral, encoding), Some(n), "{n} in {encoding:?}");
+            }
+        }
+        let print = |ast: &AST| ast_to_string(ast, PrintOptions::default());
+        assert_eq!(print(&encode(2, Encoding::Church)), "λf.λx.f (f x)");
+        assert_eq!(print(&encode(1, Encoding::Scott)), "λs.λz.s λs.λz.z");
+        assert_eq!(
+            print(&encode(1, Encoding::Parigot)),
+            "λs.λz.s (λs.λz.z) ((λs.λz.z) s z)"
+        );
+    }
+
+    #[test]
+    fn literals() {
+        for encoding in ENCODINGS {
+            let options = ParseOptions {
+                encoding,
+                ..ParseOptions::default()
+            };
+            let ast = parse("3", options).unwrap();
+            assert_eq!(decode(&ast, encoding), Some(3));
+            let print = PrintOptions {
+                numerals: Some(encoding),
+                ..PrintOptions::default()
+            };
+            assert_eq!(ast_to_string(&ast, print), "3");
+            let too_big = (encoding.limit() + 1).to_string();
+            assert!(parse(&too_big, options).is_err());
+        }
+    }
+
+    #[test]
+    fn decoding_ignores_binder_names_only() {
+        let parse = |input: &str| parse(input, ParseOptions::default()).unwrap();
+        assert_eq!(decode(&parse("λg.λy.g (g y)"), Encoding::Church), Some(2));
+        assert_eq!(decode(&parse("λa.λb.a λc.λd.d"), Encoding::Scott), Some(1));
+        // Each encoding's numerals are not numerals of the others.
+        for (i, encoding) in ENCODINGS.into_iter().enumerate() {
+            let numeral = encode(2, encoding);
+            for (j, other) in ENCODINGS.into_iter().enumerate() {
+                assert_eq!(decode(&numeral, other).is_some(), i == j);
+            }
+        }
+        // Not a numeral: the binders are the same, or the copies differ.
+        assert_eq!(decode(&parse("λx.λx.x"), Encoding::Church), None);
+        let mismatched = parse("λs.λz.s (λs.λz.z) ((λs.λz.s (λs.λz.z) z) s z)");
+        assert_eq!(decode(&mismatched, Encoding::Parigot), None);
+    }
+}
@@ -0,0 +1,353 @@
+//! A Wadler-style pretty printer. Lisp output is built the same way and
+//! laid out on one line.
+//!
+//! Terms are first turned into a `Doc`, which is then laid out to fit a
+//! given width, breaking lines only where a group does not fit on the
+//! current one. Parentheses are kept to the minimum the parser needs:
+//! application is left-associative, and an abstraction extends as far right
+//! as possible, so it only needs parentheses when something follows it.
+//! Parsing the output in the same dialect gives back an identical AST, as
+//! long as `print_numerals` is unset (numerals read back with their
+//! canonical binder names), `let` nodes are read back with `keep_let`, and
+//! the term contains no `AST::Error` placeholders.
+
+use super::strategy::Branch;
+use super::traverse::{fold, Children, Fold};
+use super::{numerals, Dialect, PrintOptions, AST, ERROR_NAME};
+
+pub enum Doc {
+    Text(String),
+    // A line break, or the given text when the enclosing group fits flat.
+    Line(&'static str),
+    Concat(Vec<Doc>),
+    // Indent the line breaks inside by this many more columns.
+    Nest(usize, Box<Doc>),
+    // Lay out flat if it fits in the remaining width, broken otherwise.
+    Group(Box<Doc>),
+}
+
+fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+fn parens(doc: Doc) -> Doc {
+    Doc::Concat(vec![text("("), doc, text(")")])
+}
+
+/// Render a document, breaking groups that do not fit in `width` columns.
+pub fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    // (indent, flat, doc), processed last-in first-out.
+    let mut stack = vec![(0, false, doc)];
+    while let Some((indent, flat, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            }
+            Doc::Line(alternative) if flat => {
+                out.push_str(alternative);
+                column += alternative.chars().count();
+            }
+            Doc::Line(_) => {
+                out.push('\n');
+                out.extend(std::iter::repeat_n(' ', indent));
+                column = indent;
+            }
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, flat, d))),
+            Doc::Nest(extra, doc) => stack.push((indent + extra, flat, doc)),
+            Doc::Group(doc) => {
+                let flat = flat || fits(width.saturating_sub(column), doc, &stack);
+                stack.push((indent, flat, doc));
+            }
+        }
+    }
+    out
+}
+
+// Whether `group` laid out flat, followed by the rest of the line, fits in
+// `remaining` columns.
+fn fits(mut remaining: usize, group: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut stack = vec![(true, group)];
+    let mut rest = rest.iter().rev();
+    loop {
+        let (flat, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, flat, doc)) => (flat, doc),
+                None => return true,
+            },
+        };
+        let len = match doc {
+            Doc::Text(s) => s.chars().count(),
+            Doc::Line(alternative) if flat => alternative.chars().count(),
+            // A line break ends the line being measured.
+            Doc::Line(_) => return true,
+            Doc::Concat(docs) => {
+                stack.extend(docs.iter().rev().map(|d| (flat, d)));
+                continue;
+            }
+            Doc::Nest(_, doc) | Doc::Group(doc) => {
+                stack.push((flat, doc));
+                continue;
+            }
+        };
+        if len > remaining {
+            return false;
+        }
+        remaining -= len;
+    }
+}
+
+// How a printed term fits into what surrounds it.
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    // A single token, which never needs parentheses.
+    Atom,
+    // An abstraction or `let`, which extends as far right as possible.
+    Open,
+    Application,
+}
+
+// A term printed bottom up, kept in pieces while the node above may still
+// add to it.
+enum Printed<'a> {
+    Done(Doc, Shape),
+    // Directly nested abstractions, innermost binder first, and their body.
+    Binders(Vec<&'a str>, Doc),
+    // The head of an application spine and its arguments.
+    Spine((Doc, Shape), Vec<(Doc, Shape)>),
+}
+
+impl Printed<'_> {
+    fn finish(self, options: PrintOptions) -> (Doc, Shape) {
+        match self {
+            Printed::Done(doc, shape) => (doc, shape),
+            Printed::Binders(mut params, body) => {
+                params.reverse();
+                if options.dialect == Dialect::Lisp {
+                    let open = text(format!("(lambda ({}) ", params.join(" ")));
+                    return (Doc::Concat(vec![open, body, text(")")]), Shape::Atom);
+                }
+                let (mut lambda, between, separator, gap) = options.dialect.binder_syntax();
+                if options.ascii && options.dialect == Dialect::Standard {
+                    lambda = "\\";
+                }
+                let doc = Doc::Group(Box::new(Doc::Concat(vec![
+                    text(format!("{}{}{}", lambda, params.join(between), separator)),
+                    Doc::Nest(2, Box::new(Doc::Concat(vec![Doc::Line(gap), body]))),
+                ])));
+                (doc, Shape::Open)
+            }
+            Printed::Spine((head, shape), args) => {
+                if options.dialect == Dialect::Lisp {
+                    let mut docs = vec![text("("), head];
+                    for (arg, _) in args {
+                        docs.extend([text(" "), arg]);
+                    }
+                    docs.push(text(")"));
+                    return (Doc::Concat(docs), Shape::Atom);
+                }
+                let head = match shape {
+                    Shape::Atom => head,
+                    _ => parens(head),
+                };
+                let space = options.dialect.application_space();
+                let last = args.len() - 1;
+                let args = args
+                    .into_iter()
+                    .enumerate()
+                    .flat_map(|(i, (arg, shape))| {
+                        let doc = match shape {
+                            Shape::Atom => arg,
+                            // Only the last argument may run on to the right.
+                            Shape::Open if i == last => arg,
+                            _ => parens(arg),
+                        };
+                        [Doc::Line(space), doc]
+                    })
+                    .collect();
+                let doc = Doc::Group(Box::new(Doc::Concat(vec![
+                    head,
+                    Doc::Nest(2, Box::new(Doc::Concat(args))),
+                ])));
+                (doc, Shape::Application)
+            }
+        }
+    }
+}
+
+struct Printer {
+    options: PrintOptions,
+    // Whether the node about to be visited is printed as part of its
+    // parent, so is not to be read as a numeral on its own.
+    merging: bool,
+}
+
+impl<'a> Fold<'a, AST> for Printer {
+    type Output = Printed<'a>;
+
+    fn descend(&mut self, node: &'a AST, branch: Branch) {
+        self.merging = match (node, branch) {
+            (AST::Lambda { body, .. }, Branch::Body) => {
+                self.options.compact_binders && matches!(**body, AST::Lambda { .. })
+            }
+            (AST::App(left, _), Branch::Function) => matches!(**left, AST::App(..)),
+            _ => false,
+        };
+    }
+
+    fn visit(&mut self, node: &'a AST) -> Option<Printed<'a>> {
+        let merging = std::mem::take(&mut self.merging);
+        let doc = match node {
+            AST::Var(name) => text(name.clone()),
+            AST::Error => text(ERROR_NAME),
+            _ if merging => return None,
+            _ => {
+                let n = numerals::decode(node, self.options.numerals?)?;
+                text(n.to_string())
+            }
+        };
+        Some(Printed::Done(doc, Shape::Atom))
+    }
+
+    fn build(&mut self, node: &'a AST, children: Children<Printed<'a>>) -> Printed<'a> {
+        let options = self.options;
+        match (node, children) {
+            (AST::Lambda { param, .. }, Children::One(body)) => match body {
+                Printed::Binders(mut params, body) if options.compact_binders => {
+                    params.push(param);
+                    Printed::Binders(params, body)
+                }
+                body => Printed::Binders(vec![param], body.finish(options).0),
+            },
+            (AST::App(..), Children::Two(left, right)) => {
+                let right = right.finish(options);
+                match left {
+                    Printed::Spine(head, mut args) => {
+                        args.push(right);
+                        Printed::Spine(head, args)
+                    }
+                    left => Printed::Spine(left.finish(options), vec![right]),
+                }
+            }
+            (AST::Let { name, fixpoint, .. }, Children::Two(value, body)) => {
+                let keyword = if fixpoint.is_some() { "letrec" } else { "let" };
+                let doc = Doc::Group(Box::new(Doc::Concat(vec![
+                    text(format!("{} {} =", keyword, name)),
+                    Doc::Nest(
+                        2,
+                        Box::new(Doc::Concat(vec![Doc::Line(" "), value.finish(options).0])),
+                    ),
+                    Doc::Line(" "),
+                    text("in "),
+                    body.finish(options).0,
+                ])));
+                Printed::Done(doc, Shape::Open)
+            }
+            _ => unreachable!("children match the node"),
+        }
+    }
+}
+
+/// Build the document for a term. Lisp has no `let`, so any in `ast` must
+/// have been expanded first.
+pub fn term(ast: &AST, options: PrintOptions) -> Doc {
+    let mut printer = Printer {
+        options,
+        merging: false,
+    };
+    fold(ast, &mut printer).finish(options).0
+}
+
+impl Drop for Doc {
+    fn drop(&mut self) {
+        // Move the insides out first, as for `AST`, so that no document is
+        // dropped with others still inside it.
+        let mut orphans = Vec::new();
+        take_insides(self, &mut orphans);
+        while let Some(mut orphan) = orphans.pop() {
+            take_insides(&mut orphan, &mut orphans);
+        }
+    }
+}
+
+fn take_insides(doc: &mut Doc, orphans: &mut Vec<Doc>) {
+    match doc {
+        Doc::Text(_) | Doc::Line(_) => {}
+        Doc::Concat(docs) => orphans.append(docs),
+        Doc::Nest(_, inner) | Doc::Group(inner) => {
+            orphans.push(std::mem::replace(&mut **inner, Doc::Concat(Vec::new())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast_to_string, parse, Dialect, ParseOptions, PrintOptions};
+
+    const TERMS: [&str; 6] = [
+        "λf x.f (f x)",
+        "(λx.x) (λy.y) z",
+        "f (g x) (λy.y) (λz.z z)",
+        "a (b c) d (λx.x) e",
+        "(λs.λz.s (λs.λz.z) ((λs.λz.z) s z)) (λp q.q p) (λu.u)",
+        "let twice = λf x.f (f x) in letrec loop = λn.loop n in twice loop",
+    ];
+
+    #[test]
+    fn minimal_parentheses() {
+        let print = |input: &str| {
+            let ast = parse(input, ParseOptions::default()).unwrap();
+            ast_to_string(&ast, PrintOptions::default())
+        };
+        assert_eq!(print("((λx.(x)) ((y)))"), "(λx.x) y");
+        assert_eq!(print("(f (λx.x)) a"), "f (λx.x) a");
+        assert_eq!(print("f (a (λx.x))"), "f (a λx.x)");
+    }
+
+    #[test]
+    fn width() {
+        let ast = parse("f (g x) (λy.y) (λz.z z)", ParseOptions::default()).unwrap();
+        let print = |width| {
+            let options = PrintOptions {
+                width,
+                ..PrintOptions::default()
+            };
+            ast_to_string(&ast, options)
+        };
+        assert_eq!(print(None), "f (g x) (λy.y) λz.z z");
+        assert_eq!(print(Some(80)), print(None));
+        assert_eq!(print(Some(20)), "f\n  (g x)\n  (λy.y)\n  λz.z z");
+    }
+
+    #[test]
+    fn printing_round_trips() {
+        let keep_let = ParseOptions {
+            keep_let: true,
+            ..ParseOptions::default()
+        };
+        for input in TERMS {
+            let ast = parse(input, keep_let).unwrap();
+            for dialect in [Dialect::Standard, Dialect::Haskell, Dialect::Latex] {
+                for width in [None, Some(20), Some(1)] {
+                    for compact_binders in [false, true] {
+                        let print = PrintOptions {
+                            dialect,
+                            width,
+                            compact_binders,
+                            ..PrintOptions::default()
+                        };
+                        let printed = ast_to_string(&ast, print);
+                        let options = ParseOptions {
+                            dialect,
+                            ..keep_let
+                        };
+                        assert_eq!(parse(&printed, options).unwrap(), ast, "{printed}");
+                    }
+                }
+            }
+        }
+    }
+}
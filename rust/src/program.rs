@@ -0,0 +1,383 @@
+//! Programs: a list of `NAME = term;` definitions followed by a main term.
+//!
+//! Definitions are expanded into the main term before anything else sees it.
+//! Names are resolved with proper scoping, so a binder shadows a definition
+//! of the same name, and every definition must be closed over the others:
+//! undefined and cyclic references are errors. Free variables in the main
+//! term are left alone, as they are ordinary free variables there.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::strategy::Branch;
+use super::traverse::{self, fold, Children, Fold, Scope};
+use super::{Comment, Dialect, ParseError, Parser, Span, Token, TokenKind, AST};
+
+pub struct Definition {
+    pub name: String,
+    pub name_span: Span,
+    // From the name up to and including the ';'.
+    pub span: Span,
+    pub body: AST,
+    // Every identifier in the body with its location, for error reporting.
+    uses: Vec<(String, Span)>,
+}
+
+pub struct Program {
+    pub definitions: Vec<Definition>,
+    pub main: AST,
+}
+
+impl Parser {
+    // Parse definitions for as long as the input looks like `NAME =`, then
+    // the main term, which may be followed by an optional ';'.
+    pub(crate) fn parse_program(&mut self) -> Result<Program, ParseError> {
+        if self.options.dialect == Dialect::Lisp {
+            return self.parse_lisp_program();
+        }
+        let mut definitions = Vec::new();
+        while let (Some(Token::Identifier(name)), Some(Token::Equals)) =
+            (self.peek(), self.peek_at(1))
+        {
+            let name = name.clone();
+            let name_pos = self.pos;
+            self.next();
+            self.next();
+            let body = self.parse_application()?;
+            if self.peek() == Some(&Token::Semicolon) {
+                self.next();
+            } else {
+                self.report(self.error("Expected ';' after definition", &[TokenKind::Semicolon]))?;
+                // Resynchronise on the next ';'.
+                while let Some(tok) = self.next() {
+                    if tok == Token::Semicolon {
+                        break;
+                    }
+                }
+            }
+            definitions.push(self.definition(name, name_pos, body));
+        }
+        let main = self.parse_application()?;
+        if self.peek() == Some(&Token::Semicolon) {
+            self.next();
+        }
+        Ok(Program { definitions, main })
+    }
+
+    // Build a definition whose name is the token at `name_pos` and which
+    // ends with the token just consumed.
+    pub(crate) fn definition(&self, name: String, name_pos: usize, body: AST) -> Definition {
+        let name_span = self.span_at(name_pos);
+        let end = self.pos.min(self.tokens.len());
+        let uses = self.tokens[(name_pos + 1).min(end)..end]
+            .iter()
+            .filter_map(|(tok, span)| match tok {
+                Token::Identifier(name) => Some((name.clone(), *span)),
+                _ => None,
+            })
+            .collect();
+        Definition {
+            name,
+            name_span,
+            span: Span::new(name_span.start, self.span_at(self.pos - 1).end),
+            body,
+            uses,
+        }
+    }
+}
+
+/// A definition together with the comments written directly above it.
+#[derive(Serialize)]
+pub struct DefinitionComments {
+    pub name: String,
+    pub span: Span,
+    pub comments: Vec<Comment>,
+}
+
+impl Program {
+    /// Attach each comment to a definition: a comment that starts on the
+    /// line where a definition ends belongs to that definition, and any
+    /// other comment to the definition it precedes. `input` is the source
+    /// the spans point into. Comments after the last definition, other than
+    /// on its last line, belong to none.
+    pub fn attach_comments(&self, input: &str, comments: &[Comment]) -> Vec<DefinitionComments> {
+        // The number of line breaks before each offset.
+        let mut breaks = vec![0];
+        for c in input.chars() {
+            let before = breaks[breaks.len() - 1];
+            breaks.push(before + usize::from(c == '\n'));
+        }
+        let line = |offset: usize| breaks[offset.min(breaks.len() - 1)];
+        let mut attached = vec![Vec::new(); self.definitions.len()];
+        for comment in comments {
+            // The first definition that does not end before the comment.
+            let next = self
+                .definitions
+                .partition_point(|def| def.span.end <= comment.span.start);
+            let owner = match next.checked_sub(1) {
+                Some(previous)
+                    if line(self.definitions[previous].span.end) == line(comment.span.start) =>
+                {
+                    Some(previous)
+                }
+                _ => self
+                    .definitions
+                    .get(next)
+                    .filter(|def| comment.span.end <= def.span.start)
+                    .map(|_| next),
+            };
+            if let Some(owner) = owner {
+                attached[owner].push(comment.clone());
+            }
+        }
+        self.definitions
+            .iter()
+            .zip(attached)
+            .map(|(def, comments)| DefinitionComments {
+                name: def.name.clone(),
+                span: def.span,
+                comments,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unvisited,
+    Visiting,
+    Done,
+}
+
+struct Resolver<'a> {
+    definitions: &'a [Definition],
+    index: HashMap<&'a str, usize>,
+    state: Vec<State>,
+    resolved: Vec<AST>,
+    // Definitions currently being expanded, innermost last.
+    path: Vec<usize>,
+    errors: Vec<ParseError>,
+}
+
+// A term being expanded, with the binders around the current node.
+struct Expansion<'r, 'a, 't> {
+    resolver: &'r mut Resolver<'a>,
+    scope: Scope<'t>,
+}
+
+impl<'t> Fold<'t, AST> for Expansion<'_, '_, 't> {
+    type Output = AST;
+
+    fn visit(&mut self, node: &'t AST) -> Option<AST> {
+        match node {
+            AST::Var(name) if self.scope.get(name).is_none() => self.resolver.reference(name),
+            _ => None,
+        }
+    }
+
+    fn descend(&mut self, node: &'t AST, branch: Branch) {
+        if let Some(name) = traverse::binder(node, branch) {
+            self.scope.bind(name, ());
+        }
+    }
+
+    fn ascend(&mut self, node: &'t AST, branch: Branch) {
+        if let Some(name) = traverse::binder(node, branch) {
+            self.scope.unbind(name);
+        }
+    }
+
+    fn build(&mut self, node: &'t AST, children: Children<AST>) -> AST {
+        traverse::rebuild(node, children)
+    }
+}
+
+impl Program {
+    /// Expand every definition into the main term. Errors are collected
+    /// rather than returned one at a time; the term is still usable when
+    /// there are errors, with offending references left as free variables.
+    pub fn resolve(&self) -> (AST, Vec<ParseError>) {
+        let mut resolver = Resolver {
+            definitions: &self.definitions,
+            index: HashMap::new(),
+            state: vec![State::Unvisited; self.definitions.len()],
+            resolved: vec![AST::Error; self.definitions.len()],
+            path: Vec::new(),
+            errors: Vec::new(),
+        };
+        for (i, def) in self.definitions.iter().enumerate() {
+            if resolver.index.insert(&def.name, i).is_some() {
+                resolver.errors.push(ParseError {
+                    message: format!("Duplicate definition of '{}'", def.name),
+                    span: def.name_span,
+                    found: Some(Token::Identifier(def.name.clone())),
+                    expected: Vec::new(),
+                });
+            }
+        }
+        for i in 0..self.definitions.len() {
+            resolver.resolve(i);
+        }
+        let main = resolver.expand(&self.main);
+        let mut errors = resolver.errors;
+        errors.sort_by_key(|e| e.span.start);
+        (main, errors)
+    }
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, i: usize) {
+        match self.state[i] {
+            State::Done => return,
+            State::Visiting => {
+                let start = self.path.iter().position(|&j| j == i).unwrap_or(0);
+                let cycle: Vec<&str> = self.path[start..]
+                    .iter()
+                    .chain(std::iter::once(&i))
+                    .map(|&j| self.definitions[j].name.as_str())
+                    .collect();
+                let def = &self.definitions[i];
+                self.errors.push(ParseError {
+                    message: format!("Cyclic definition: {}", cycle.join(" -> ")),
+                    span: def.name_span,
+                    found: Some(Token::Identifier(def.name.clone())),
+                    expected: Vec::new(),
+                });
+                return;
+            }
+            State::Unvisited => {}
+        }
+        self.state[i] = State::Visiting;
+        self.path.push(i);
+        let definitions = self.definitions;
+        let body = self.expand(&definitions[i].body);
+        self.path.pop();
+        self.resolved[i] = body;
+        self.state[i] = State::Done;
+    }
+
+    // Replace free occurrences of defined names. Resolved definitions are
+    // closed terms, so splicing them in can never capture a variable.
+    fn expand(&mut self, ast: &AST) -> AST {
+        fold(
+            ast,
+            &mut Expansion {
+                resolver: self,
+                scope: Scope::new(),
+            },
+        )
+    }
+
+    // What a free occurrence of `name` expands to, if it is defined.
+    fn reference(&mut self, name: &str) -> Option<AST> {
+        match self.index.get(name) {
+            Some(&j) => {
+                self.resolve(j);
+                // Unless it is part of a cycle that has already been reported.
+                (self.state[j] == State::Done).then(|| self.resolved[j].clone())
+            }
+            None => {
+                if let Some(&current) = self.path.last() {
+                    self.undefined(current, name);
+                }
+                None
+            }
+        }
+    }
+
+    fn undefined(&mut self, current: usize, name: &str) {
+        let def = &self.definitions[current];
+        let span = def
+            .uses
+            .iter()
+            .find(|(used, _)| used == name)
+            .map_or(def.name_span, |(_, span)| *span);
+        let message = format!("Undefined name '{}' in definition of '{}'", name, def.name);
+        if !self.errors.iter().any(|e| e.message == message) {
+            self.errors.push(ParseError {
+                message,
+                span,
+                found: Some(Token::Identifier(name.to_string())),
+                expected: Vec::new(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast_to_string, definition_comments_internal, parse, ParseOptions, PrintOptions, Span, Token,
+    };
+
+    fn expand(input: &str) -> String {
+        let ast = parse(input, ParseOptions::default()).expect("the program parses");
+        ast_to_string(&ast, PrintOptions::default())
+    }
+
+    #[test]
+    fn binders_shadow_definitions() {
+        assert_eq!(expand("I = λx.x; K = λx y.x; K I a"), "(λx.λy.x) (λx.x) a");
+        // `I` under `λI` is the binder's, not the definition's.
+        assert_eq!(expand("I = λx.x; λI.I a"), "λI.I a");
+        assert_eq!(expand("I = λx.x; T = λI.I I; T"), "λI.I I");
+        // Free variables of the main term are left alone.
+        assert_eq!(expand("I = λx.x; I y"), "(λx.x) y");
+    }
+
+    #[test]
+    fn undefined_and_cyclic_definitions() {
+        let error = |input: &str| parse(input, ParseOptions::default()).unwrap_err();
+
+        let e = error("A = λx.x C; A");
+        assert_eq!(e.message, "Undefined name 'C' in definition of 'A'");
+        assert_eq!(e.span, Span::new(9, 10));
+        assert_eq!(e.found, Some(Token::Identifier("C".to_string())));
+
+        let e = error("A = λx.B x; B = λy.A y; A");
+        assert_eq!(e.message, "Cyclic definition: A -> B -> A");
+        assert_eq!(e.span, Span::new(0, 1));
+
+        let e = error("A = λa.a; A = λb.b; A");
+        assert_eq!(e.message, "Duplicate definition of 'A'");
+        assert_eq!(e.span, Span::new(10, 11));
+    }
+
+    #[test]
+    fn comments_attach_to_the_next_definition() {
+        let input = "-- identity\nI = λx.x; -- trailing\n{- constant {- nested -} -}\nK = λx y.x;\n-- main\nK I";
+        let definitions = definition_comments_internal(input, ParseOptions::default()).unwrap();
+        let attached: Vec<(&str, Vec<&str>)> = definitions
+            .iter()
+            .map(|def| {
+                let texts = def.comments.iter().map(|c| c.text.as_str()).collect();
+                (def.name.as_str(), texts)
+            })
+            .collect();
+        assert_eq!(
+            attached,
+            [
+                ("I", vec!["identity", "trailing"]),
+                ("K", vec!["constant {- nested -}"]),
+            ]
+        );
+        assert_eq!(definitions[0].comments[0].span, Span::new(0, 11));
+        assert_eq!(expand(input), "(λx.λy.x) λx.x");
+    }
+
+    #[test]
+    fn trailing_comments_stay_on_their_line() {
+        let input =
+            "A = x; -- about A\n-- about B\nB = y; {- also B -} C = z; -- about C\n-- main\nA";
+        let definitions = definition_comments_internal(input, ParseOptions::default()).unwrap();
+        let attached: Vec<Vec<&str>> = definitions
+            .iter()
+            .map(|def| def.comments.iter().map(|c| c.text.as_str()).collect())
+            .collect();
+        assert_eq!(
+            attached,
+            [vec!["about A"], vec!["about B", "also B"], vec!["about C"]]
+        );
+    }
+}
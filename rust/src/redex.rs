@@ -0,0 +1,188 @@
+//! Every redex of a term, so that the user rather than a strategy can pick
+//! which one to contract next.
+
+use super::nameless::{Node, Term};
+use super::strategy::{Branch, Contraction, Reduction, StepKind};
+use super::traverse::{fold, Children, Fold};
+use super::AST;
+
+/// A redex and where it is in the term.
+pub struct Redex {
+    pub path: Vec<Branch>,
+    pub kind: StepKind,
+    pub variable: String,
+    pub argument: AST,
+}
+
+impl Redex {
+    /// The redex of the given kind at the end of `path` in `ast`.
+    pub fn at(ast: &AST, path: Vec<Branch>, kind: StepKind) -> Redex {
+        let node = subterm(ast, &path).expect("the path leads to the redex");
+        let (variable, argument) = match (kind, node) {
+            (StepKind::Beta, AST::App(function, argument)) => match &**function {
+                AST::Lambda { param, .. } => (param, argument),
+                _ => unreachable!("a β-redex applies an abstraction"),
+            },
+            (StepKind::Let, AST::Let { name, value, .. }) => (name, value),
+            (StepKind::Eta, AST::Lambda { param, body }) => match &**body {
+                AST::App(function, _) => (param, function),
+                _ => unreachable!("an η-redex abstracts an application"),
+            },
+            _ => unreachable!("the node is a redex of that kind"),
+        };
+        Redex {
+            path,
+            kind,
+            variable: variable.clone(),
+            argument: (**argument).clone(),
+        }
+    }
+}
+
+/// Every β-redex and kept `let` in `ast`, and every η-redex too under
+/// `Reduction::BetaEta`, outermost first and then from left to right, the
+/// order normal-order reduction would find them in.
+pub fn redexes(ast: &AST, reduction: Reduction) -> Vec<Redex> {
+    let mut collector = Collector {
+        reduction,
+        path: Vec::new(),
+        found: Vec::new(),
+    };
+    fold(&Term::from_ast(ast), &mut collector);
+    collector
+        .found
+        .into_iter()
+        .map(|(path, kind)| Redex::at(ast, path, kind))
+        .collect()
+}
+
+struct Collector {
+    reduction: Reduction,
+    path: Vec<Branch>,
+    found: Vec<(Vec<Branch>, StepKind)>,
+}
+
+impl<'t> Fold<'t, Term> for Collector {
+    type Output = ();
+
+    fn visit(&mut self, term: &'t Term) -> Option<()> {
+        let kind = match &**term {
+            Node::App(left, _) if matches!(**left, Node::Lambda { .. }) => Some(StepKind::Beta),
+            Node::Let { .. } => Some(StepKind::Let),
+            Node::Lambda { .. } if self.reduction == Reduction::BetaEta && term.is_eta_redex() => {
+                Some(StepKind::Eta)
+            }
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            self.found.push((self.path.clone(), kind));
+        }
+        None
+    }
+
+    fn descend(&mut self, _: &'t Term, branch: Branch) {
+        self.path.push(branch);
+    }
+
+    fn ascend(&mut self, _: &'t Term, _: Branch) {
+        self.path.pop();
+    }
+
+    fn build(&mut self, _: &'t Term, _: Children<()>) {}
+}
+
+fn child(ast: &AST, branch: Branch) -> Option<&AST> {
+    match (ast, branch) {
+        (AST::App(left, _), Branch::Function) => Some(left),
+        (AST::App(_, right), Branch::Argument) => Some(right),
+        (AST::Lambda { body, .. } | AST::Let { body, .. }, Branch::Body) => Some(body),
+        (AST::Let { value, .. }, Branch::Value) => Some(value),
+        _ => None,
+    }
+}
+
+/// The node at the end of `path`, if there is one.
+pub fn subterm<'a>(ast: &'a AST, path: &[Branch]) -> Option<&'a AST> {
+    let mut node = ast;
+    for &branch in path {
+        node = child(node, branch)?;
+    }
+    Some(node)
+}
+
+/// Contract the redex at the end of `path`, or return `None` if the path
+/// does not lead to one. η-redexes are contracted whatever the reduction
+/// mode, since the user asked for this one.
+pub fn reduce_at(term: &Term, path: &[Branch]) -> Option<Contraction> {
+    let node = term.subterm(path)?;
+    let (kind, reduct) = node
+        .contract()
+        .or_else(|| Some((StepKind::Eta, node.eta_contract()?)))?;
+    Some(Contraction::at(term, path.to_vec(), kind, reduct))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast_to_string, parse, redexes_internal, Options, ParseOptions, PrintOptions, Span,
+    };
+
+    fn print(ast: &AST) -> String {
+        ast_to_string(ast, PrintOptions::default())
+    }
+
+    #[test]
+    fn redexes_with_spans() {
+        use Branch::*;
+        let input = "(λx.x) ((λy.y) a) (λz.(λw.w) z)";
+        let found: Vec<_> = redexes_internal(input, Options::default())
+            .unwrap()
+            .into_iter()
+            .map(|redex| (redex.path, redex.span, redex.variable, redex.argument))
+            .collect();
+        let redex = |path: Vec<Branch>, start, end, variable: &str, argument: &str| {
+            let span = Some(Span::new(start, end));
+            (path, span, variable.to_string(), argument.to_string())
+        };
+        assert_eq!(
+            found,
+            [
+                redex(vec![Function], 0, 17, "x", "(λy.y) a"),
+                redex(vec![Function, Argument], 8, 16, "y", "a"),
+                redex(vec![Argument, Body], 22, 30, "w", "z"),
+            ]
+        );
+        // A redex inside a definition is located where it is used.
+        let found = redexes_internal("I = λx.x; I a", Options::default()).unwrap();
+        assert_eq!(found[0].span, Some(Span::new(10, 13)));
+    }
+
+    #[test]
+    fn reduce_at_a_chosen_redex() {
+        use Branch::*;
+        let ast = parse("(λx.x) ((λy.y) a) (λz.(λw.w) z)", ParseOptions::default()).unwrap();
+        let term = Term::from_ast(&ast);
+        let after = |contraction: Contraction| print(&contraction.term.to_ast(&mut Vec::new()));
+        for (path, expected) in [
+            (vec![Function], "(λy.y) a λz.(λw.w) z"),
+            (vec![Function, Argument], "(λx.x) a λz.(λw.w) z"),
+            (vec![Argument, Body], "(λx.x) ((λy.y) a) λz.z"),
+        ] {
+            let contraction = reduce_at(&term, &path).expect("a redex");
+            assert_eq!(contraction.path, path);
+            assert_eq!(after(contraction), expected);
+        }
+        // Not a redex, and not a node at all.
+        assert!(reduce_at(&term, &[]).is_none());
+        assert!(reduce_at(&term, &[Argument, Argument]).is_none());
+        // η-redexes can be picked even though `redexes` only lists them
+        // under βη.
+        assert!(!redexes(&ast, Reduction::Beta)
+            .iter()
+            .any(|redex| redex.path == [Argument]));
+        let contraction = reduce_at(&term, &[Argument]).expect("an η-redex");
+        assert_eq!(contraction.kind, StepKind::Eta);
+        assert_eq!(after(contraction), "(λx.x) ((λy.y) a) λw.w");
+    }
+}
@@ -0,0 +1,410 @@
+//! Reduction strategies: which redex a single step contracts.
+//!
+//! | strategy           | redex chosen       | under λ | stops at               |
+//! |--------------------|--------------------|---------|------------------------|
+//! | `NormalOrder`      | leftmost-outermost | yes     | normal form            |
+//! | `ApplicativeOrder` | leftmost-innermost | yes     | normal form            |
+//! | `CallByName`       | leftmost-outermost | no      | weak normal form       |
+//! | `CallByValue`      | leftmost-innermost | no      | weak normal form       |
+//! | `Head`             | head redex only    | yes     | head normal form       |
+//! | `WeakHead`         | head redex only    | no      | weak head normal form  |
+//! | `Parallel`         | every redex at once| yes     | normal form            |
+//!
+//! Call-by-value only contracts a redex whose argument is a value, that is
+//! a variable or an abstraction. A kept `let` is expanded as soon as a
+//! strategy reaches it. A `Parallel` step is a complete development: it
+//! contracts every redex of the term, including those nested in one
+//! another, but none of the redexes this creates (see the `development`
+//! module).
+//!
+//! With `Reduction::BetaEta`, the strategies that reduce under λ also
+//! contract η-redexes `λx.M x` (where `x` is not free in `M`) to `M`, treating
+//! the abstraction as the redex's position.
+
+use serde::{Deserialize, Serialize};
+
+use super::nameless::{Node, Term};
+use super::redex::Redex;
+use super::traverse::{Children, Tree};
+use super::{development, free_vars, fresh_var, Fixpoint, Rename, AST};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Strategy {
+    #[default]
+    NormalOrder,
+    ApplicativeOrder,
+    CallByName,
+    CallByValue,
+    Head,
+    WeakHead,
+    Parallel,
+}
+
+impl Strategy {
+    fn under_lambda(self) -> bool {
+        matches!(
+            self,
+            Strategy::NormalOrder
+                | Strategy::ApplicativeOrder
+                | Strategy::Head
+                | Strategy::Parallel
+        )
+    }
+
+    /// The fixed-point combinator `letrec` should use under this strategy:
+    /// `Y` loops forever when arguments are evaluated first.
+    pub fn fixpoint(self) -> Fixpoint {
+        match self {
+            Strategy::ApplicativeOrder | Strategy::CallByValue => Fixpoint::Z,
+            _ => Fixpoint::Y,
+        }
+    }
+}
+
+/// Which notion of reduction a step may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Reduction {
+    /// β only.
+    #[default]
+    Beta,
+    /// β and η, so that normal forms are βη-normal forms.
+    BetaEta,
+}
+
+/// Which child of a node a path goes through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Branch {
+    /// The left side of an application.
+    Function,
+    /// The right side of an application.
+    Argument,
+    /// The body of an abstraction or a `let`.
+    Body,
+    /// The value bound by a `let`.
+    Value,
+}
+
+/// What kind of node a step contracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum StepKind {
+    /// `(λx.M) N`: `variable` is `x` and `argument` is `N`.
+    Beta,
+    /// A kept `let` or `letrec`: `variable` is the name it binds and
+    /// `argument` the value bound to it.
+    Let,
+    /// `λx.M x`: `variable` is `x` and `argument` is `M`.
+    Eta,
+}
+
+/// One reduction step.
+pub struct Step {
+    /// The whole term after the step.
+    pub term: AST,
+    /// From the root to the node that was contracted.
+    pub path: Vec<Branch>,
+    pub kind: StepKind,
+    pub variable: String,
+    pub argument: AST,
+    /// Binders renamed to avoid capture, in the order they were renamed.
+    pub renamed: Vec<Rename>,
+    /// The paths to every redex the step contracted, outermost first: just
+    /// `path`, except for a complete development, where `path`, `kind`,
+    /// `variable` and `argument` describe the first of them.
+    pub developed: Vec<Vec<Branch>>,
+}
+
+/// Contract one redex, chosen by `strategy`. Returns whether anything was
+/// reduced, together with the new term (or the old one, shared).
+pub fn beta_reduce(term: &Term, strategy: Strategy, reduction: Reduction) -> (bool, Term) {
+    match step(term, strategy, reduction) {
+        Some(contraction) => (true, contraction.term),
+        None => (false, term.clone()),
+    }
+}
+
+/// Take one step, recording where it happened, or `None` if the term is in
+/// normal form for `strategy`. Only the redex and the nodes above it are
+/// rebuilt; `Step::new` describes the step with named terms.
+pub fn step(term: &Term, strategy: Strategy, reduction: Reduction) -> Option<Contraction> {
+    Some(choose(term, strategy, reduction)?.apply(term))
+}
+
+/// What a strategy contracts next in a nameless term.
+pub enum Choice {
+    /// The redex of the given kind at the end of `path`, and its reduct.
+    Redex {
+        path: Vec<Branch>,
+        kind: StepKind,
+        reduct: Term,
+    },
+    /// A complete development, which rebuilds the whole term.
+    Development(Contraction),
+}
+
+/// Find what `strategy` contracts next in a nameless term, or return `None`
+/// if the term is in normal form for `strategy`.
+pub fn choose(term: &Term, strategy: Strategy, reduction: Reduction) -> Option<Choice> {
+    if strategy == Strategy::Parallel {
+        return development::develop(term, reduction).map(Choice::Development);
+    }
+    let (path, kind, reduct) = step_at(term, strategy, reduction)?;
+    Some(Choice::Redex { path, kind, reduct })
+}
+
+impl Choice {
+    /// From the root to the redex, or to the first of them.
+    pub fn path(&self) -> &[Branch] {
+        match self {
+            Choice::Redex { path, .. } => path,
+            Choice::Development(contraction) => &contraction.path,
+        }
+    }
+
+    /// Make the contraction in `term`, the term it was chosen in, sharing
+    /// every node of `term` outside the redex.
+    pub fn apply(self, term: &Term) -> Contraction {
+        match self {
+            Choice::Redex { path, kind, reduct } => Contraction::at(term, path, kind, reduct),
+            Choice::Development(contraction) => contraction,
+        }
+    }
+}
+
+impl Step {
+    /// Describe `contraction`, made in the nameless form of `before`.
+    pub fn new(before: &AST, contraction: &Contraction) -> Step {
+        let redex = Redex::at(before, contraction.path.clone(), contraction.kind);
+        let mut renamed = contraction.renamed.clone();
+        Step {
+            term: contraction.term.to_ast(&mut renamed),
+            path: redex.path,
+            kind: contraction.kind,
+            variable: redex.variable,
+            argument: redex.argument,
+            renamed,
+            developed: contraction.developed.clone(),
+        }
+    }
+}
+
+/// A redex contracted somewhere inside a nameless term.
+pub struct Contraction {
+    /// The whole term after contracting it.
+    pub term: Term,
+    /// From the root to the redex.
+    pub path: Vec<Branch>,
+    pub kind: StepKind,
+    /// As for `Step`.
+    pub renamed: Vec<Rename>,
+    /// As for `Step`.
+    pub developed: Vec<Vec<Branch>>,
+}
+
+impl Contraction {
+    /// The redex of the given kind at the end of `path` in `term`, replaced
+    /// by `reduct`. Only binders in the reduct can capture a variable that
+    /// did not get captured before, so only those are renamed, and the
+    /// term never needs renaming when it is read back.
+    pub fn at(term: &Term, path: Vec<Branch>, kind: StepKind, reduct: Term) -> Contraction {
+        let mut renamed = Vec::new();
+        let reduct = match reduct.respelled(&term.binders_along(&path), &mut renamed) {
+            Some(respelled) => respelled,
+            None => reduct,
+        };
+        Contraction {
+            term: term.replace(&path, reduct),
+            developed: vec![path.clone()],
+            path,
+            kind,
+            renamed,
+        }
+    }
+}
+
+fn is_value(term: &Term) -> bool {
+    matches!(**term, Node::Bound(_) | Node::Free(_) | Node::Lambda { .. })
+}
+
+/// η-expand `ast` to `λx.ast x`, with `x` not free in `ast`.
+pub fn eta_expand(ast: &AST) -> AST {
+    let param = fresh_var(&free_vars(ast), "x");
+    AST::Lambda {
+        body: Box::new(AST::App(
+            Box::new(ast.clone()),
+            Box::new(AST::Var(param.clone())),
+        )),
+        param,
+    }
+}
+
+// A redex found in a term: the path to it from the root, its kind and its
+// reduct.
+type Found = (Vec<Branch>, StepKind, Term);
+
+enum Task<'t> {
+    // Look for the redex at or below a node, which is down `branch` from the
+    // node at the end of the first `depth` branches of the path.
+    Enter(&'t Term, usize, Option<Branch>),
+    // Try a node itself, once nothing below it was found. The path to it is
+    // `depth` branches long.
+    Leave(&'t Term, usize),
+}
+
+// Find the redex `strategy` contracts, searching depth first and from left
+// to right: an innermost-first strategy tries a node after everything below
+// it, and any other before. A kept `let` is expanded as soon as it is
+// reached.
+fn step_at(term: &Term, strategy: Strategy, reduction: Reduction) -> Option<Found> {
+    let innermost = matches!(strategy, Strategy::ApplicativeOrder | Strategy::CallByValue);
+    let mut path = Vec::new();
+    let mut tasks = vec![Task::Enter(term, 0, None)];
+    while let Some(task) = tasks.pop() {
+        let node = match task {
+            Task::Leave(node, depth) => {
+                path.truncate(depth);
+                node
+            }
+            Task::Enter(node, depth, branch) => {
+                path.truncate(depth);
+                path.extend(branch);
+                if matches!(**node, Node::Lambda { .. }) && !strategy.under_lambda() {
+                    continue;
+                }
+                if !innermost || matches!(**node, Node::Let { .. }) {
+                    if let Some((kind, reduct)) = contract_here(node, strategy, reduction) {
+                        return Some((path, kind, reduct));
+                    }
+                } else {
+                    tasks.push(Task::Leave(node, path.len()));
+                }
+                let depth = path.len();
+                let mut push =
+                    |(branch, child)| tasks.push(Task::Enter(child, depth, Some(branch)));
+                match node.children() {
+                    Children::Zero => {}
+                    Children::One(child) => push(child),
+                    // The function part of a head redex is never an
+                    // abstraction here, so only the spine is searched.
+                    Children::Two(function, _)
+                        if matches!(strategy, Strategy::Head | Strategy::WeakHead) =>
+                    {
+                        push(function)
+                    }
+                    Children::Two(first, second) => {
+                        push(second);
+                        push(first);
+                    }
+                }
+                continue;
+            }
+        };
+        if let Some((kind, reduct)) = contract_here(node, strategy, reduction) {
+            return Some((path, kind, reduct));
+        }
+    }
+    None
+}
+
+// Contract `node` itself, if `strategy` takes it as a redex.
+fn contract_here(
+    node: &Term,
+    strategy: Strategy,
+    reduction: Reduction,
+) -> Option<(StepKind, Term)> {
+    match &**node {
+        Node::Lambda { .. } => match reduction {
+            Reduction::Beta => None,
+            Reduction::BetaEta => Some((StepKind::Eta, node.eta_contract()?)),
+        },
+        Node::App(_, argument) if strategy == Strategy::CallByValue && !is_value(argument) => None,
+        _ => node.contract(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize;
+    use crate::{ast_to_string, parse, ParseOptions, PrintOptions};
+
+    const STRATEGIES: [Strategy; 6] = [
+        Strategy::NormalOrder,
+        Strategy::ApplicativeOrder,
+        Strategy::CallByName,
+        Strategy::CallByValue,
+        Strategy::Head,
+        Strategy::WeakHead,
+    ];
+
+    fn parse_default(input: &str) -> AST {
+        parse(input, ParseOptions::default()).expect("the term parses")
+    }
+
+    fn print(ast: &AST) -> String {
+        ast_to_string(ast, PrintOptions::default())
+    }
+
+    #[test]
+    fn strategies_on_a_discarded_loop() {
+        let input = "(λx.y) ((λx.x x) λx.x x)";
+        let ast = parse_default(input);
+        // Innermost-first strategies contract Ω, which steps to itself.
+        let after = ["y", input, "y", input, "y", "y"];
+        for (strategy, expected) in STRATEGIES.into_iter().zip(after) {
+            let mut term = Term::from_ast(&ast);
+            for _ in 0..3 {
+                let (reduced, next) = beta_reduce(&term, strategy, Reduction::Beta);
+                assert!(reduced || expected == "y", "{strategy:?}");
+                term = next;
+            }
+            assert_eq!(
+                print(&term.to_ast(&mut Vec::new())),
+                expected,
+                "{strategy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn where_strategies_stop() {
+        // Which strategies take a step in each term.
+        let table = [
+            ("λz.(λx.x) z", [true, true, false, false, true, false]),
+            ("x ((λy.y) z)", [true, true, true, true, false, false]),
+            ("(λx.x) (λy.y)", [true; 6]),
+        ];
+        for (input, reduces) in table {
+            let term = Term::from_ast(&parse_default(input));
+            for (strategy, reduces) in STRATEGIES.into_iter().zip(reduces) {
+                let (reduced, _) = beta_reduce(&term, strategy, Reduction::Beta);
+                assert_eq!(reduced, reduces, "{strategy:?} on {input}");
+            }
+        }
+    }
+
+    #[test]
+    fn eta() {
+        let normalize = |input: &str, reduction| {
+            let ast = parse_default(input);
+            print(&normalize::normalize(ast, Strategy::NormalOrder, reduction, 100).term)
+        };
+        for (input, beta, beta_eta) in [
+            ("λx.f x", "λx.f x", "f"),
+            ("λx.(λy.y) f x", "λx.f x", "f"),
+            ("λx.λy.g x y", "λx.λy.g x y", "g"),
+            // `x` is free in `x x`, so this is no η-redex.
+            ("λx.x x", "λx.x x", "λx.x x"),
+        ] {
+            assert_eq!(normalize(input, Reduction::Beta), beta);
+            assert_eq!(normalize(input, Reduction::BetaEta), beta_eta);
+        }
+        // Strategies that stay out of abstractions leave η-redexes alone.
+        let term = Term::from_ast(&parse_default("λx.f x"));
+        assert!(step(&term, Strategy::CallByName, Reduction::BetaEta).is_none());
+
+        let expand = |input: &str| print(&eta_expand(&parse_default(input)));
+        assert_eq!(expand("f"), "λx.f x");
+        assert_eq!(expand("f x"), "λx1.f x x1");
+        assert_eq!(normalize(&expand("λy.g y"), Reduction::BetaEta), "g");
+    }
+}
@@ -0,0 +1,127 @@
+//! Terms nested far more deeply than a recursive traversal could follow on
+//! the wasm stack.
+
+use super::*;
+use normalize::Outcome;
+
+const DEPTH: usize = 100_000;
+
+// `λf.λx.f (f (… (f x)))` with `DEPTH` applications, written with brackets.
+fn church(redex_inside: bool) -> String {
+    let innermost = if redex_inside { "(λy.y) x" } else { "x" };
+    format!(
+        "λf x.{}{}{}",
+        "f (".repeat(DEPTH),
+        innermost,
+        ")".repeat(DEPTH)
+    )
+}
+
+fn parse_default(input: &str) -> AST {
+    parse(input, ParseOptions::default()).expect("the term parses")
+}
+
+#[test]
+fn deep_right_spine() {
+    let input = church(true);
+    let ast = parse_default(&input);
+    assert!(free_vars(&ast).is_empty());
+    assert_eq!(ast.clone(), ast);
+
+    let result = normalize::normalize(ast, Strategy::NormalOrder, Reduction::Beta, 10);
+    assert_eq!(result.outcome, Outcome::NormalForm);
+    assert_eq!(result.steps, 1);
+    assert_eq!(
+        ast_to_string(&result.term, PrintOptions::default()),
+        ast_to_string(&parse_default(&church(false)), PrintOptions::default()),
+    );
+}
+
+#[test]
+fn deep_abstractions() {
+    let input = format!("{}x y", "λx.".repeat(DEPTH));
+    let ast = parse_default(&input);
+    assert_eq!(free_vars(&ast), HashSet::from(["y".to_string()]));
+    let term = nameless::Term::from_ast(&ast);
+    assert!(alpha::alpha_equivalent(&term.to_ast(&mut Vec::new()), &ast));
+    let printed = ast_to_string(&ast, PrintOptions::default());
+    assert_eq!(parse_default(&printed), ast);
+}
+
+#[test]
+fn deep_left_spine() {
+    let input = format!("{}x{}", "(".repeat(DEPTH), " y)".repeat(DEPTH));
+    let ast = parse_default(&input);
+    assert_eq!(parse_default(&format!("x{}", " y".repeat(DEPTH))), ast);
+    let result = normalize::normalize(ast, Strategy::ApplicativeOrder, Reduction::Beta, 10);
+    assert_eq!(result.outcome, Outcome::NormalForm);
+    assert_eq!(result.steps, 0);
+}
+
+#[test]
+fn deep_lisp() {
+    let options = ParseOptions {
+        dialect: Dialect::Lisp,
+        ..ParseOptions::default()
+    };
+    let input = format!(
+        "(lambda (f x) {}x{})",
+        "(f ".repeat(DEPTH),
+        ")".repeat(DEPTH)
+    );
+    let ast = parse(&input, options).expect("the term parses");
+    assert_eq!(ast, parse_default(&church(false)));
+    let print = PrintOptions {
+        dialect: Dialect::Lisp,
+        ..PrintOptions::default()
+    };
+    assert_eq!(
+        parse(&ast_to_string(&ast, print), options).expect("reparses"),
+        ast
+    );
+}
+
+#[test]
+fn nesting_limit() {
+    let options = ParseOptions {
+        max_nesting: 1000,
+        ..ParseOptions::default()
+    };
+    let input = format!("{}x{}", "(".repeat(1001), ")".repeat(1001));
+    let error = parse(&input, options).expect_err("nested too deeply");
+    assert!(error.message.contains("nested too deeply"));
+    let (ast, errors) = parse_recovering(&input, options);
+    assert_eq!(ast, AST::Error);
+    assert_eq!(errors.len(), 1);
+
+    let input = format!("{}x", "λx.".repeat(1001));
+    assert!(parse(&input, options).is_err());
+    let input = format!("{}x", "λx.".repeat(1000));
+    assert!(parse(&input, options).is_ok());
+}
+
+#[test]
+fn deep_machines() {
+    let nest = |outer: &str, depth: usize| format!("{}z{}", outer.repeat(depth), ")".repeat(depth));
+    // `λp.λs.s p` makes a closure holding its argument's, so the CEK
+    // machine ends up with environments nested `DEPTH` closures deep.
+    let cases = [
+        ("λy.y", "z".to_string(), "z".to_string()),
+        (
+            "λp.λs.s p",
+            format!("λs.s ({})", nest("(λp.λs.s p) (", DEPTH - 1)),
+            nest("λs.s (", DEPTH),
+        ),
+    ];
+    for (function, krivine, cek) in cases {
+        let ast = parse_default(&format!("({}) ({}) z", church(false), function));
+        for (kind, expected) in [(machine::Kind::Krivine, krivine), (machine::Kind::Cek, cek)] {
+            let mut machine = machine::Machine::new(kind, &nameless::Term::from_ast(&ast));
+            while machine.step().is_some() {}
+            assert_eq!(
+                machine.term().to_ast(&mut Vec::new()),
+                parse_default(&expected)
+            );
+        }
+    }
+}
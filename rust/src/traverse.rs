@@ -0,0 +1,370 @@
+//! Traversals that keep their own stack.
+//!
+//! Terms can be nested far more deeply than the native stack (which is
+//! small under wasm) allows a recursive function to follow, so everything
+//! that walks a whole term goes through `fold`, which keeps the nodes still
+//! to be visited, and the results of those already visited, on the heap.
+//!
+//! The `AST` implementations of `Clone`, `PartialEq`, `Hash` and `Drop` live
+//! here too, since the derived ones recurse.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+use super::strategy::Branch;
+use super::AST;
+
+/// The children of a node, in source order.
+pub enum Children<T> {
+    Zero,
+    One(T),
+    Two(T, T),
+}
+
+/// A term that `fold` can walk.
+pub trait Tree {
+    /// The node's children, with the branch leading to each.
+    fn children(&self) -> Children<(Branch, &Self)>;
+}
+
+/// What a fold computes at each node.
+pub trait Fold<'a, T: ?Sized> {
+    type Output;
+
+    /// Called on the way down. A result stands for the whole subtree, whose
+    /// children are then skipped.
+    fn visit(&mut self, _node: &'a T) -> Option<Self::Output> {
+        None
+    }
+
+    /// Called just before going down `branch` of `node`.
+    fn descend(&mut self, _node: &'a T, _branch: Branch) {}
+
+    /// Called just after coming back up `branch` of `node`.
+    fn ascend(&mut self, _node: &'a T, _branch: Branch) {}
+
+    /// Combine the results for the children of `node`, once they are all
+    /// known.
+    fn build(&mut self, node: &'a T, children: Children<Self::Output>) -> Self::Output;
+}
+
+impl<T> Children<T> {
+    fn take_first(&mut self) -> Option<T> {
+        match std::mem::replace(self, Children::Zero) {
+            Children::Zero => None,
+            Children::One(first) => Some(first),
+            Children::Two(first, second) => {
+                *self = Children::One(second);
+                Some(first)
+            }
+        }
+    }
+}
+
+// A node whose children are being folded.
+struct Frame<'a, T> {
+    node: &'a T,
+    // The children not yet gone down.
+    rest: Children<(Branch, &'a T)>,
+    // How many children have been gone down.
+    entered: usize,
+    // The branch of the child being folded, if any.
+    branch: Option<Branch>,
+}
+
+/// How deep `fold` recurses before keeping its stack on the heap instead,
+/// which is slower for the shallow terms that are by far the most common.
+pub const NATIVE_DEPTH: usize = 128;
+
+/// Fold `root` bottom up, depth first and children in source order.
+pub fn fold<'a, T: Tree, F: Fold<'a, T>>(root: &'a T, folder: &mut F) -> F::Output {
+    fold_within(root, folder, NATIVE_DEPTH)
+}
+
+fn fold_within<'a, T: Tree, F: Fold<'a, T>>(node: &'a T, folder: &mut F, room: usize) -> F::Output {
+    if room == 0 {
+        return fold_on_heap(node, folder);
+    }
+    if let Some(result) = folder.visit(node) {
+        return result;
+    }
+    let child = |folder: &mut F, (branch, child)| {
+        folder.descend(node, branch);
+        let result = fold_within(child, folder, room - 1);
+        folder.ascend(node, branch);
+        result
+    };
+    let children = match node.children() {
+        Children::Zero => Children::Zero,
+        Children::One(only) => Children::One(child(folder, only)),
+        Children::Two(first, second) => {
+            let first = child(folder, first);
+            Children::Two(first, child(folder, second))
+        }
+    };
+    folder.build(node, children)
+}
+
+fn fold_on_heap<'a, T: Tree, F: Fold<'a, T>>(root: &'a T, folder: &mut F) -> F::Output {
+    let mut frames: Vec<Frame<'a, T>> = Vec::new();
+    let mut results = Vec::new();
+    let mut reached = Some(root);
+    loop {
+        if let Some(node) = reached.take() {
+            match folder.visit(node) {
+                Some(result) => results.push(result),
+                None => frames.push(Frame {
+                    node,
+                    rest: node.children(),
+                    entered: 0,
+                    branch: None,
+                }),
+            }
+        }
+        let Some(frame) = frames.last_mut() else {
+            return results.pop().expect("root folded");
+        };
+        if let Some(branch) = frame.branch.take() {
+            folder.ascend(frame.node, branch);
+        }
+        if let Some((branch, child)) = frame.rest.take_first() {
+            folder.descend(frame.node, branch);
+            frame.branch = Some(branch);
+            frame.entered += 1;
+            reached = Some(child);
+            continue;
+        }
+        let children = match frame.entered {
+            0 => Children::Zero,
+            1 => Children::One(results.pop().expect("child folded")),
+            _ => {
+                let second = results.pop().expect("child folded");
+                let first = results.pop().expect("child folded");
+                Children::Two(first, second)
+            }
+        };
+        let result = folder.build(frame.node, children);
+        frames.pop();
+        results.push(result);
+    }
+}
+
+impl Tree for AST {
+    fn children(&self) -> Children<(Branch, &AST)> {
+        match self {
+            AST::Var(_) | AST::Error => Children::Zero,
+            AST::Lambda { body, .. } => Children::One((Branch::Body, body)),
+            AST::App(left, right) => {
+                Children::Two((Branch::Function, left), (Branch::Argument, right))
+            }
+            AST::Let { value, body, .. } => {
+                Children::Two((Branch::Value, value), (Branch::Body, body))
+            }
+        }
+    }
+}
+
+/// The name whose scope going down `branch` of `ast` enters, if any.
+pub fn binder(ast: &AST, branch: Branch) -> Option<&str> {
+    match (ast, branch) {
+        (AST::Lambda { param: name, .. } | AST::Let { name, .. }, Branch::Body) => Some(name),
+        (
+            AST::Let {
+                name,
+                fixpoint: Some(_),
+                ..
+            },
+            Branch::Value,
+        ) => Some(name),
+        _ => None,
+    }
+}
+
+/// The binders around the node a fold is at, looked up by name in constant
+/// time, each with a value of the fold's choosing.
+pub struct Scope<'a, T = ()> {
+    bindings: HashMap<&'a str, Vec<T>, BuildHasherDefault<NameHasher>>,
+}
+
+impl<'a, T> Scope<'a, T> {
+    pub fn new() -> Self {
+        Scope {
+            bindings: HashMap::default(),
+        }
+    }
+
+    pub fn bind(&mut self, name: &'a str, value: T) {
+        self.bindings.entry(name).or_default().push(value);
+    }
+
+    pub fn unbind(&mut self, name: &str) {
+        if let Some(values) = self.bindings.get_mut(name) {
+            values.pop();
+        }
+    }
+
+    /// The value of the innermost binder called `name`.
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.bindings.get(name)?.last()
+    }
+}
+
+/// A copy of `node` with the given children in place of its own.
+pub fn rebuild(node: &AST, children: Children<AST>) -> AST {
+    match (node, children) {
+        (AST::Var(name), _) => AST::Var(name.clone()),
+        (AST::Lambda { param, .. }, Children::One(body)) => AST::Lambda {
+            param: param.clone(),
+            body: Box::new(body),
+        },
+        (AST::App(..), Children::Two(left, right)) => AST::App(Box::new(left), Box::new(right)),
+        (AST::Let { name, fixpoint, .. }, Children::Two(value, body)) => AST::Let {
+            name: name.clone(),
+            value: Box::new(value),
+            body: Box::new(body),
+            fixpoint: *fixpoint,
+        },
+        _ => AST::Error,
+    }
+}
+
+// Hashes the short names in a scope faster than the default hasher, which
+// guards against collisions chosen by an attacker at some cost (this is
+// FxHash, from the Rust compiler).
+#[derive(Default)]
+pub struct NameHasher(u64);
+
+impl Hasher for NameHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 =
+                (self.0.rotate_left(5) ^ u64::from(byte)).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+struct Cloner;
+
+impl<'a> Fold<'a, AST> for Cloner {
+    type Output = AST;
+
+    fn build(&mut self, node: &'a AST, children: Children<AST>) -> AST {
+        rebuild(node, children)
+    }
+}
+
+impl Clone for AST {
+    fn clone(&self) -> AST {
+        fold(self, &mut Cloner)
+    }
+}
+
+impl PartialEq for AST {
+    fn eq(&self, other: &AST) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some(pair) = pairs.pop() {
+            match pair {
+                (AST::Var(a), AST::Var(b)) if a == b => {}
+                (AST::Error, AST::Error) => {}
+                (AST::Lambda { param: a, body: x }, AST::Lambda { param: b, body: y })
+                    if a == b =>
+                {
+                    pairs.push((x, y));
+                }
+                (AST::App(f, x), AST::App(g, y)) => {
+                    pairs.push((f, g));
+                    pairs.push((x, y));
+                }
+                (
+                    AST::Let {
+                        name: a,
+                        value: v,
+                        body: x,
+                        fixpoint: p,
+                    },
+                    AST::Let {
+                        name: b,
+                        value: w,
+                        body: y,
+                        fixpoint: q,
+                    },
+                ) if a == b && p == q => {
+                    pairs.push((v, w));
+                    pairs.push((x, y));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Eq for AST {}
+
+impl Hash for AST {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            std::mem::discriminant(node).hash(state);
+            match node {
+                AST::Var(name) => name.hash(state),
+                AST::Error => {}
+                AST::Lambda { param, body } => {
+                    param.hash(state);
+                    nodes.push(body);
+                }
+                AST::App(left, right) => {
+                    nodes.push(right);
+                    nodes.push(left);
+                }
+                AST::Let {
+                    name,
+                    value,
+                    body,
+                    fixpoint,
+                } => {
+                    name.hash(state);
+                    fixpoint.hash(state);
+                    nodes.push(body);
+                    nodes.push(value);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for AST {
+    fn drop(&mut self) {
+        // Move the children out before they are dropped, so that each node
+        // is dropped without any below it.
+        let mut orphans = Vec::new();
+        take_children(self, &mut orphans);
+        while let Some(mut orphan) = orphans.pop() {
+            take_children(&mut orphan, &mut orphans);
+        }
+    }
+}
+
+fn take_children(ast: &mut AST, orphans: &mut Vec<AST>) {
+    let mut take = |child: &mut Box<AST>| {
+        if !matches!(**child, AST::Var(_) | AST::Error) {
+            orphans.push(std::mem::replace(&mut **child, AST::Error));
+        }
+    };
+    match ast {
+        AST::Var(_) | AST::Error => {}
+        AST::Lambda { body, .. } => take(body),
+        AST::App(left, right) => {
+            take(left);
+            take(right);
+        }
+        AST::Let { value, body, .. } => {
+            take(value);
+            take(body);
+        }
+    }
+}